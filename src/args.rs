@@ -1,28 +1,137 @@
-use clap::Parser;
+use clap::{ArgGroup, Parser, Subcommand};
+use crate::{dither, effects, lut, mono, palette, shapes, theme, tone};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
+#[command(group(ArgGroup::new("ansi_mode").args(["ansi", "ansi16", "ansi24"]).multiple(true)))]
 pub struct Args {
     /// image url or file path
-    #[arg(index = 1)]
-    pub image: String,
+    #[arg(index = 1, required = true)]
+    pub image: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
 
     /// irc
-    #[arg(long, default_value_t = false)]
+    #[arg(long, global = true, default_value_t = false)]
     pub irc: bool,
 
+    /// 16 colour irc
+    #[arg(long, global = true, default_value_t = false)]
+    pub irc16: bool,
+
     /// 8-bit ansi
-    #[arg(long, default_value_t = false)]
+    #[arg(long, global = true, default_value_t = false)]
     pub ansi: bool,
 
+    /// 4-bit ansi
+    #[arg(long, global = true, default_value_t = false)]
+    pub ansi16: bool,
+
+    /// rgb values of the 16 ansi colours (xterm, vga, campbell or 16 comma separated colours)
+    #[arg(long, global = true, default_value = "xterm", value_parser = palette::parse_ansi16_colors)]
+    pub ansi16_colors: palette::Ansi16Colors,
+
+    /// terminal colour scheme overriding the 16 basic colours (xresources, alacritty, itermcolors, windows terminal)
+    #[arg(long, global = true, value_parser = theme::load_theme)]
+    pub term_theme: Option<palette::Ansi16Colors>,
+
+    /// exclude the 16 basic colours from 8-bit ansi matching
+    #[arg(long, global = true, default_value_t = false)]
+    pub nobasic: bool,
+
     /// 24-bit ansi
-    #[arg(long, default_value_t = false)]
+    #[arg(long, global = true, default_value_t = false)]
     pub ansi24: bool,
 
+    /// bits kept per channel in 24-bit ansi (1 to 8)
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(1..=8), requires = "ansi24")]
+    pub ansi24_bits: u8,
+
+    /// skip repeated colour sequences in ansi output and report the size saved
+    #[arg(long, default_value_t = false, requires = "ansi_mode")]
+    pub compact: bool,
+
+    /// sixel graphics, 256 adaptive colours unless --irc, --irc16, --ansi or --ansi16 picks a palette
+    #[arg(long, default_value_t = false)]
+    pub sixel: bool,
+
+    /// character cell size in pixels ("<width>x<height>"), sets the cell aspect and scales --sixel output to --width columns
+    #[arg(long = "cell-size", value_parser = parse_cell_size)]
+    pub cell_pixels: Option<(u32, u32)>,
+
+    /// kitty graphics preview of the processed image before it is quantized
+    #[arg(long, default_value_t = false, conflicts_with = "sixel")]
+    pub kitty: bool,
+
     /// quarterblock
     #[arg(long, default_value_t = false)]
     pub qb: bool,  
 
+    /// left/right halfblocks, 2×1 pixels per character
+    #[arg(long, default_value_t = false, conflicts_with = "qb")]
+    pub vertical: bool,
+
+    /// sextants, 2×3 pixels per character
+    #[arg(long, default_value_t = false, conflicts_with_all = ["qb", "vertical"])]
+    pub sextant: bool,
+
+    /// octants, 2×4 pixels per character
+    #[arg(long, default_value_t = false, conflicts_with_all = ["qb", "vertical", "sextant"])]
+    pub octant: bool,
+
+    /// braille dots, 2×4 pixels per character in one colour
+    #[arg(long, default_value_t = false, conflicts_with_all = ["qb", "vertical", "sextant", "octant"])]
+    pub braille: bool,
+
+    /// --braille fills the unlit dots with a bg colour
+    #[arg(long, default_value_t = false, requires = "braille")]
+    pub braille_bg: bool,
+
+    /// ascii characters picked by luma from --ramp
+    #[arg(long, default_value_t = false, conflicts_with_all = ["qb", "vertical", "sextant", "octant", "braille"])]
+    pub ascii: bool,
+
+    /// glyphs picked by shape from --glyphs, matched at 4×8 pixels per character
+    #[arg(long, default_value_t = false, conflicts_with_all = ["qb", "vertical", "sextant", "octant", "braille", "ascii"])]
+    pub shapes: bool,
+
+    /// --shapes glyph sets
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "eighths,shades,quadrants", requires = "shapes")]
+    pub glyphs: Vec<shapes::GlyphSet>,
+
+    /// bg coloured spaces only, --width is in pixels
+    #[arg(long, default_value_t = false, conflicts_with_all = ["qb", "vertical", "sextant", "octant", "braille", "ascii", "shapes"])]
+    pub spaces: bool,
+
+    /// --spaces characters per pixel (1 or 2, 2 is about square)
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(1..=2), requires = "spaces")]
+    pub space_width: u8,
+
+    /// --ascii characters from dark to light
+    #[arg(long, default_value = " .:-=+*#%@", value_parser = parse_ramp, requires = "ascii")]
+    pub ramp: String,
+
+    /// mix palette colours with shade glyphs (not with --ansi24)
+    #[arg(long, default_value_t = false, conflicts_with_all = ["qb", "vertical", "sextant", "octant", "braille", "ascii", "shapes", "spaces", "ansi24"])]
+    pub shade: bool,
+
+    /// two colour rendering
+    #[arg(long, default_value_t = false)]
+    pub mono: bool,
+
+    /// --mono and --braille luma threshold (0 to 255, or auto)
+    #[arg(long, default_value = "auto", value_parser = mono::parse_threshold)]
+    pub threshold: mono::Threshold,
+
+    /// --mono colour above the threshold
+    #[arg(long, default_value = "#ffffff", value_parser = palette::parse_hex_color)]
+    pub mono_fg: u32,
+
+    /// --mono colour below the threshold
+    #[arg(long, default_value = "#000000", value_parser = palette::parse_hex_color)]
+    pub mono_bg: u32,
+
     /// image width to resize to
     #[arg(short, long, default_value_t = 50)]
     pub width: u32,
@@ -47,10 +156,54 @@ pub struct Args {
     #[arg(short, long, default_value_t = 0.0)]
     pub gamma: f32,
 
+    /// levels ("<black>,<white>[,<gamma>]", black and white 0 to 255, gamma 0.1 to 10)
+    #[arg(long, value_parser = tone::parse_levels)]
+    pub levels: Option<tone::Levels>,
+
+    /// rgb curve ("<in>:<out>,<in>:<out>,...", 0 to 255)
+    #[arg(long, value_parser = tone::parse_curve)]
+    pub curve: Option<tone::Curve>,
+
+    /// red curve, applied before --curve
+    #[arg(long, value_parser = tone::parse_curve)]
+    pub curve_red: Option<tone::Curve>,
+
+    /// green curve, applied before --curve
+    #[arg(long, value_parser = tone::parse_curve)]
+    pub curve_green: Option<tone::Curve>,
+
+    /// blue curve, applied before --curve
+    #[arg(long, value_parser = tone::parse_curve)]
+    pub curve_blue: Option<tone::Curve>,
+
+    /// shadows colour balance ("<red>,<green>,<blue>", -100 to 100)
+    #[arg(long, allow_hyphen_values = true, value_parser = tone::parse_balance)]
+    pub shadows: Option<tone::Balance>,
+
+    /// midtones colour balance ("<red>,<green>,<blue>", -100 to 100)
+    #[arg(long, allow_hyphen_values = true, value_parser = tone::parse_balance)]
+    pub midtones: Option<tone::Balance>,
+
+    /// highlights colour balance ("<red>,<green>,<blue>", -100 to 100)
+    #[arg(long, allow_hyphen_values = true, value_parser = tone::parse_balance)]
+    pub highlights: Option<tone::Balance>,
+
     /// dither (1 to 8)
     #[arg(long, default_value_t = 0)]
     pub dither: u32,
 
+    /// dither while matching against the palette, or the --braille dots (floyd-steinberg for --irc16, --ansi16)
+    #[arg(long, value_enum)]
+    pub palette_dither: Option<dither::DitherMethod>,
+
+    /// palette dithering strength (0 to 1)
+    #[arg(long, default_value_t = 1.0, value_parser = parse_strength)]
+    pub dither_strength: f32,
+
+    /// no palette dithering
+    #[arg(long, default_value_t = false)]
+    pub nodither: bool,
+
     /// pixelize size
     #[arg(long, default_value_t = 0)]
     pub pixelize: i32,
@@ -63,14 +216,38 @@ pub struct Args {
     #[arg(long)]
     pub oil: Option<String>,
 
+    /// gradient map ("<colour>,<colour>,...", dark to light)
+    #[arg(long, value_parser = effects::parse_gradient)]
+    pub gradient_map: Option<effects::Gradient>,
+
+    /// colour grade with a 1D or 3D .cube lut
+    #[arg(long, value_parser = lut::load_lut)]
+    pub lut: Option<lut::Lut>,
+
     /// grayscale
     #[arg(long, default_value_t = false)]
     pub grayscale: bool,
 
     /// no grayscale
-    #[arg(long, default_value_t = false)]
+    #[arg(long, global = true, default_value_t = false)]
     pub nograyscale: bool,
 
+    /// match only against the black, white and gray palette entries
+    #[arg(long, default_value_t = false, conflicts_with = "nograyscale")]
+    pub grayscale_palette: bool,
+
+    /// palette indices to never match ("0,1,99")
+    #[arg(long, value_delimiter = ',')]
+    pub exclude_colors: Vec<u8>,
+
+    /// limit the palette to the n colours that best fit the image
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    pub max_colors: Option<u8>,
+
+    /// compress colours into the palette's gamut before matching
+    #[arg(long, default_value_t = false)]
+    pub gamut_map: bool,
+
     /// halftone
     #[arg(long, default_value_t = false)]
     pub halftone: bool,
@@ -168,6 +345,162 @@ pub struct Args {
     pub edge_detection: bool,
 }
 
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// inspect and convert palettes
+    Palette {
+        #[command(subcommand)]
+        action: PaletteAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PaletteAction {
+    /// print labelled swatches of a palette in the current render type
+    Show {
+        /// irc, irc16, ansi, ansi16 or a palette file (.gpl or terminal colour scheme)
+        palette: String,
+    },
+
+    /// print the nearest index to a colour in each palette and metric
+    Nearest {
+        /// colour as #RRGGBB
+        #[arg(value_parser = palette::parse_hex_color)]
+        color: u32,
+    },
+
+    /// export the dominant colours of an image as a gimp palette
+    Extract {
+        /// image url or file path
+        image: String,
+
+        /// number of colours
+        #[arg(short, default_value_t = 16, value_parser = clap::value_parser!(u8).range(1..))]
+        n: u8,
+
+        /// write to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+impl Args {
+    // --ansi24 only applies when no palette render type was asked for
+    pub fn truecolor(&self) -> bool {
+        self.ansi24 && !(self.irc || self.irc16 || self.ansi || self.ansi16)
+    }
+
+    // the layout flag given, halfblocks otherwise
+    pub fn layout(&self) -> Layout {
+        let layouts = [
+            (self.vertical, Layout::VerticalHalfblock),
+            (self.qb, Layout::Quarterblock),
+            (self.sextant, Layout::Sextant),
+            (self.octant, Layout::Octant),
+            (self.braille, Layout::Braille),
+            (self.ascii, Layout::Ascii),
+            (self.shapes, Layout::Shapes),
+            (self.spaces, Layout::Spaces),
+            (self.shade, Layout::Shade),
+        ];
+
+        layouts
+            .iter()
+            .find(|(chosen, _)| *chosen)
+            .map_or(Layout::Halfblock, |(_, layout)| *layout)
+    }
+
+    // the encoder that runs, irc wins over the ansi modes and irc is the default
+    pub fn encoding(&self) -> Encoding {
+        match (self.irc || self.irc16, self.ansi, self.ansi16, self.ansi24) {
+            (true, _, _, _) => Encoding::Irc,
+            (_, true, _, _) => Encoding::Ansi8,
+            (_, _, true, _) => Encoding::Ansi4,
+            (_, _, _, true) => Encoding::Ansi24,
+            _ => Encoding::Irc,
+        }
+    }
+
+    // pixels per character cell for the chosen layout, (columns, rows)
+    pub fn cell_size(&self) -> (u32, u32) {
+        // square pixels, --width is in pixels unless the cell size is known
+        if self.sixel {
+            return self.cell_pixels.unwrap_or((1, 2));
+        }
+
+        match self.layout() {
+            Layout::Halfblock | Layout::Shade | Layout::Ascii => (1, 2),
+            Layout::VerticalHalfblock => (2, 1),
+            Layout::Quarterblock => (2, 2),
+            Layout::Sextant => (2, 3),
+            Layout::Octant | Layout::Braille => (2, 4),
+            Layout::Shapes => shapes::SHAPE_RESOLUTION,
+            Layout::Spaces => (1, 1),
+        }
+    }
+
+    // character cell height over width, about 2 unless --cell-size says otherwise
+    pub fn cell_aspect(&self) -> f32 {
+        self.cell_pixels.map_or(2.0, |(width, height)| height as f32 / width as f32)
+    }
+
+    // characters across each cell
+    pub fn cell_chars(&self) -> u32 {
+        match self.layout() {
+            Layout::Spaces => self.space_width as u32,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Irc,
+    Ansi8,
+    Ansi4,
+    Ansi24,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    Halfblock,
+    VerticalHalfblock,
+    Quarterblock,
+    Sextant,
+    Octant,
+    Braille,
+    Ascii,
+    Shapes,
+    Spaces,
+    Shade,
+}
+
 pub fn parse_args() -> Args {
     Args::parse()
-}
\ No newline at end of file
+}
+
+fn parse_strength(strength: &str) -> Result<f32, String> {
+    match strength.parse::<f32>() {
+        Ok(x) if (0.0..=1.0).contains(&x) => Ok(x),
+        _ => Err("expected a number from 0 to 1".to_string()),
+    }
+}
+
+fn parse_ramp(ramp: &str) -> Result<String, String> {
+    match ramp.len() {
+        _ if !ramp.bytes().all(|x| (b' '..=b'~').contains(&x)) => Err("ramp must be printable ascii".to_string()),
+        0 | 1 => Err("ramp needs at least 2 characters".to_string()),
+        _ => Ok(ramp.to_string()),
+    }
+}
+
+fn parse_cell_size(size: &str) -> Result<(u32, u32), String> {
+    let parsed = size
+        .split_once('x')
+        .map(|(width, height)| (width.trim().parse::<u32>(), height.trim().parse::<u32>()));
+
+    match parsed {
+        Some((Ok(width), Ok(height))) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err("expected \"<width>x<height>\" in pixels".to_string()),
+    }
+}
@@ -0,0 +1,251 @@
+use crate::palette::{hex_to_rgb, Palette};
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DitherMethod {
+    FloydSteinberg,
+    Atkinson,
+    Jarvis,
+    Sierra,
+    Bayer2,
+    Bayer4,
+    Bayer8,
+    BlueNoise,
+}
+
+// (dx, dy, weight) of each neighbour and the divisor of the weights
+const FLOYD_STEINBERG: (&[(i32, usize, f32)], f32) = (
+    &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)],
+    16.0,
+);
+
+// only diffuses 6/8 of the error, which keeps contrast high
+const ATKINSON: (&[(i32, usize, f32)], f32) = (
+    &[(1, 0, 1.0), (2, 0, 1.0), (-1, 1, 1.0), (0, 1, 1.0), (1, 1, 1.0), (0, 2, 1.0)],
+    8.0,
+);
+
+const JARVIS: (&[(i32, usize, f32)], f32) = (
+    &[
+        (1, 0, 7.0), (2, 0, 5.0),
+        (-2, 1, 3.0), (-1, 1, 5.0), (0, 1, 7.0), (1, 1, 5.0), (2, 1, 3.0),
+        (-2, 2, 1.0), (-1, 2, 3.0), (0, 2, 5.0), (1, 2, 3.0), (2, 2, 1.0),
+    ],
+    48.0,
+);
+
+const SIERRA: (&[(i32, usize, f32)], f32) = (
+    &[
+        (1, 0, 5.0), (2, 0, 3.0),
+        (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 5.0), (1, 1, 4.0), (2, 1, 2.0),
+        (-1, 2, 2.0), (0, 2, 3.0), (1, 2, 2.0),
+    ],
+    32.0,
+);
+
+// how far (in 0-255 channel units) ordered dithering may push a pixel at full strength
+const ORDERED_SPREAD: f32 = 64.0;
+
+const BLUE_NOISE_SIZE: usize = 16;
+
+// maps the pixel grid to palette indices, dithering inside the quantization step
+pub fn dither_hex_colors(bitmap: &[Vec<u32>], palette: &Palette, method: DitherMethod, strength: f32) -> Vec<Vec<u8>> {
+    match method {
+        DitherMethod::FloydSteinberg => error_diffusion(bitmap, palette, FLOYD_STEINBERG, strength),
+        DitherMethod::Atkinson => error_diffusion(bitmap, palette, ATKINSON, strength),
+        DitherMethod::Jarvis => error_diffusion(bitmap, palette, JARVIS, strength),
+        DitherMethod::Sierra => error_diffusion(bitmap, palette, SIERRA, strength),
+        DitherMethod::Bayer2 => ordered(bitmap, palette, &bayer_matrix(2), strength),
+        DitherMethod::Bayer4 => ordered(bitmap, palette, &bayer_matrix(4), strength),
+        DitherMethod::Bayer8 => ordered(bitmap, palette, &bayer_matrix(8), strength),
+        DitherMethod::BlueNoise => ordered(bitmap, palette, &blue_noise_matrix(BLUE_NOISE_SIZE), strength),
+    }
+}
+
+fn nearest_rgb(palette: &Palette, rgb: [f32; 3]) -> u8 {
+    let [r, g, b] = rgb.map(|x| x.clamp(0.0, 255.0) as u32);
+    palette.nearest((r << 16) + (g << 8) + b)
+}
+
+fn error_diffusion(bitmap: &[Vec<u32>], palette: &Palette, kernel: (&[(i32, usize, f32)], f32), strength: f32) -> Vec<Vec<u8>> {
+    let height = bitmap.len();
+    let width = bitmap.first().map_or(0, |row| row.len());
+    let (weights, divisor) = kernel;
+
+    let mut errors: Vec<Vec<[f32; 3]>> = vec![vec![[0.0; 3]; width]; height];
+    let mut indices: Vec<Vec<u8>> = vec![vec![0; width]; height];
+
+    for y in 0..height {
+        for x in 0..width {
+            let (r, g, b) = hex_to_rgb(bitmap[y][x]);
+            let err = errors[y][x];
+
+            let wanted = [
+                (r as f32 + err[0]).clamp(0.0, 255.0),
+                (g as f32 + err[1]).clamp(0.0, 255.0),
+                (b as f32 + err[2]).clamp(0.0, 255.0),
+            ];
+
+            let index = nearest_rgb(palette, wanted);
+            indices[y][x] = index;
+
+            let (pr, pg, pb) = hex_to_rgb(palette.colors[index as usize]);
+            let quant_error = [
+                (wanted[0] - pr as f32) * strength,
+                (wanted[1] - pg as f32) * strength,
+                (wanted[2] - pb as f32) * strength,
+            ];
+
+            for &(dx, dy, weight) in weights {
+                let nx = x as i32 + dx;
+                let ny = y + dy;
+
+                if nx < 0 || nx as usize >= width || ny >= height {
+                    continue;
+                }
+
+                for c in 0..3 {
+                    errors[ny][nx as usize][c] += quant_error[c] * weight / divisor;
+                }
+            }
+        }
+    }
+
+    indices
+}
+
+// threshold matrix values are in 0..1, centred on zero before being applied
+fn ordered(bitmap: &[Vec<u32>], palette: &Palette, matrix: &[Vec<f32>], strength: f32) -> Vec<Vec<u8>> {
+    let size = matrix.len();
+
+    bitmap
+        .iter()
+        .enumerate()
+        .map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(x, &pixel)| {
+                    let (r, g, b) = hex_to_rgb(pixel);
+                    let offset = (matrix[y % size][x % size] - 0.5) * ORDERED_SPREAD * strength;
+
+                    nearest_rgb(palette, [r as f32 + offset, g as f32 + offset, b as f32 + offset])
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// recursive bayer index matrix, size must be a power of two
+fn bayer_matrix(size: usize) -> Vec<Vec<f32>> {
+    let mut matrix = vec![vec![0u32]];
+
+    while matrix.len() < size {
+        let n = matrix.len();
+        let mut next = vec![vec![0u32; n * 2]; n * 2];
+
+        for y in 0..n {
+            for x in 0..n {
+                let v = matrix[y][x] * 4;
+                next[y][x] = v;
+                next[y][x + n] = v + 2;
+                next[y + n][x] = v + 3;
+                next[y + n][x + n] = v + 1;
+            }
+        }
+
+        matrix = next;
+    }
+
+    let cells = (size * size) as f32;
+    matrix
+        .iter()
+        .map(|row| row.iter().map(|&v| (v as f32 + 0.5) / cells).collect())
+        .collect()
+}
+
+// void-and-cluster blue noise, generated deterministically so output is reproducible
+fn blue_noise_matrix(size: usize) -> Vec<Vec<f32>> {
+    let cells = size * size;
+    let sigma = 1.5f32;
+
+    // toroidal gaussian falloff between two cells
+    let falloff = |a: usize, b: usize| {
+        let dx = (a % size).abs_diff(b % size);
+        let dy = (a / size).abs_diff(b / size);
+        let dx = dx.min(size - dx) as f32;
+        let dy = dy.min(size - dy) as f32;
+        (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+    };
+
+    let mut energy = vec![0.0f32; cells];
+    let mut pattern = vec![false; cells];
+
+    let toggle = |pattern: &mut [bool], energy: &mut [f32], cell: usize| {
+        pattern[cell] = !pattern[cell];
+        let sign = if pattern[cell] { 1.0 } else { -1.0 };
+        for (i, e) in energy.iter_mut().enumerate() {
+            *e += sign * falloff(cell, i);
+        }
+    };
+
+    let tightest_cluster = |pattern: &[bool], energy: &[f32]| {
+        (0..cells)
+            .filter(|&i| pattern[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    let largest_void = |pattern: &[bool], energy: &[f32]| {
+        (0..cells)
+            .filter(|&i| !pattern[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // seed roughly a tenth of the cells with a small lcg
+    let mut seed: u32 = 0x2545f491;
+    let initial = (cells / 10).max(1);
+    while pattern.iter().filter(|&&x| x).count() < initial {
+        seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+        let cell = (seed >> 8) as usize % cells;
+        if !pattern[cell] {
+            toggle(&mut pattern, &mut energy, cell);
+        }
+    }
+
+    // move points from clusters into voids until the pattern settles
+    loop {
+        let cluster = tightest_cluster(&pattern, &energy);
+        toggle(&mut pattern, &mut energy, cluster);
+        let void = largest_void(&pattern, &energy);
+
+        if void == cluster {
+            toggle(&mut pattern, &mut energy, void);
+            break;
+        }
+
+        toggle(&mut pattern, &mut energy, void);
+    }
+
+    let mut ranks = vec![0usize; cells];
+
+    // rank the initial points by removing the tightest clusters first
+    let (mut removed, mut removed_energy) = (pattern.clone(), energy.clone());
+    for rank in (0..initial).rev() {
+        let cluster = tightest_cluster(&removed, &removed_energy);
+        toggle(&mut removed, &mut removed_energy, cluster);
+        ranks[cluster] = rank;
+    }
+
+    // then fill the largest voids for the rest
+    for rank in initial..cells {
+        let void = largest_void(&pattern, &energy);
+        toggle(&mut pattern, &mut energy, void);
+        ranks[void] = rank;
+    }
+
+    ranks
+        .chunks(size)
+        .map(|row| row.iter().map(|&rank| (rank as f32 + 0.5) / cells as f32).collect())
+        .collect()
+}
@@ -1,45 +1,105 @@
 use crate::args;
-use crate::palette::{RGB99, RGB88, ANSI232, ANSI256, nearest_hex_color};
+use crate::dither::{dither_hex_colors, DitherMethod};
+use crate::{gamut, mono};
+use crate::palette::{Palette, map_hex_colors, hex_to_rgb, color_distance_squared, luma};
 use photon_rs::PhotonImage;
 
-// █ full
-const FULL: &str = "\u{2588}";
-
-// ▄ down
+// ▀ upper half, fg on top and bg below
 const UP: &str = "\u{2580}";
 
-// ▀ up
-const DOWN: &str = "\u{2584}";
-
-// ▌ left
+// ▌ left half, fg on the left and bg on the right
 const LEFT: &str = "\u{258C}";
 
-// ▐ right
-const RIGHT: &str = "\u{2590}";
-
-// ▞ diag_right
-const DIAG_RIGHT: &str = "\u{259E}";
-
-// ▚ diag_left
-const DIAG_LEFT: &str = "\u{259A}";
-
-// ▙ down_left (2596 prev)
-const DOWN_LEFT: &str = "\u{2599}";
-
-// ▟ down_right
-const DOWN_RIGHT: &str = "\u{259F}";
-
-// ▛ top_left
-const UP_LEFT: &str = "\u{259B}";
-
-// ▜ top_right
-const UP_RIGHT: &str = "\u{259C}";
+// quadrant glyphs indexed by mask, bit 0 top left, 1 top right, 2 bottom left, 3 bottom right
+pub const QUADRANTS: [&str; 16] = [
+    " ", "\u{2598}", "\u{259D}", "\u{2580}", "\u{2596}", "\u{258C}", "\u{259E}", "\u{259B}",
+    "\u{2597}", "\u{259A}", "\u{2590}", "\u{259C}", "\u{2584}", "\u{2599}", "\u{259F}", "\u{2588}",
+];
+
+// sextant glyphs indexed by mask, bit 0 top left to bit 5 bottom right, U+1FB00 onwards
+// skips the masks already covered by space, ▌, ▐ and █
+pub const SEXTANTS: [&str; 64] = [
+    " ", "\u{1FB00}", "\u{1FB01}", "\u{1FB02}", "\u{1FB03}", "\u{1FB04}", "\u{1FB05}", "\u{1FB06}",
+    "\u{1FB07}", "\u{1FB08}", "\u{1FB09}", "\u{1FB0A}", "\u{1FB0B}", "\u{1FB0C}", "\u{1FB0D}", "\u{1FB0E}",
+    "\u{1FB0F}", "\u{1FB10}", "\u{1FB11}", "\u{1FB12}", "\u{1FB13}", "\u{258C}", "\u{1FB14}", "\u{1FB15}",
+    "\u{1FB16}", "\u{1FB17}", "\u{1FB18}", "\u{1FB19}", "\u{1FB1A}", "\u{1FB1B}", "\u{1FB1C}", "\u{1FB1D}",
+    "\u{1FB1E}", "\u{1FB1F}", "\u{1FB20}", "\u{1FB21}", "\u{1FB22}", "\u{1FB23}", "\u{1FB24}", "\u{1FB25}",
+    "\u{1FB26}", "\u{1FB27}", "\u{2590}", "\u{1FB28}", "\u{1FB29}", "\u{1FB2A}", "\u{1FB2B}", "\u{1FB2C}",
+    "\u{1FB2D}", "\u{1FB2E}", "\u{1FB2F}", "\u{1FB30}", "\u{1FB31}", "\u{1FB32}", "\u{1FB33}", "\u{1FB34}",
+    "\u{1FB35}", "\u{1FB36}", "\u{1FB37}", "\u{1FB38}", "\u{1FB39}", "\u{1FB3A}", "\u{1FB3B}", "\u{2588}",
+];
+
+// octant glyphs indexed by mask, bit 0 top left to bit 7 bottom right, U+1CD00 onwards
+// skips the masks already covered by block and quadrant glyphs, the single octant and middle
+// quarter masks only have newer glyphs so they are left empty and drawn as their complements
+const OCTANTS: [&str; 256] = [
+    " ", "", "", "\u{1FB82}", "\u{1CD00}", "\u{2598}", "\u{1CD01}", "\u{1CD02}",
+    "\u{1CD03}", "\u{1CD04}", "\u{259D}", "\u{1CD05}", "\u{1CD06}", "\u{1CD07}", "\u{1CD08}", "\u{2580}",
+    "\u{1CD09}", "\u{1CD0A}", "\u{1CD0B}", "\u{1CD0C}", "", "\u{1CD0D}", "\u{1CD0E}", "\u{1CD0F}",
+    "\u{1CD10}", "\u{1CD11}", "\u{1CD12}", "\u{1CD13}", "\u{1CD14}", "\u{1CD15}", "\u{1CD16}", "\u{1CD17}",
+    "\u{1CD18}", "\u{1CD19}", "\u{1CD1A}", "\u{1CD1B}", "\u{1CD1C}", "\u{1CD1D}", "\u{1CD1E}", "\u{1CD1F}",
+    "", "\u{1CD20}", "\u{1CD21}", "\u{1CD22}", "\u{1CD23}", "\u{1CD24}", "\u{1CD25}", "\u{1CD26}",
+    "\u{1CD27}", "\u{1CD28}", "\u{1CD29}", "\u{1CD2A}", "\u{1CD2B}", "\u{1CD2C}", "\u{1CD2D}", "\u{1CD2E}",
+    "\u{1CD2F}", "\u{1CD30}", "\u{1CD31}", "\u{1CD32}", "\u{1CD33}", "\u{1CD34}", "\u{1CD35}", "\u{1FB85}",
+    "", "\u{1CD36}", "\u{1CD37}", "\u{1CD38}", "\u{1CD39}", "\u{1CD3A}", "\u{1CD3B}", "\u{1CD3C}",
+    "\u{1CD3D}", "\u{1CD3E}", "\u{1CD3F}", "\u{1CD40}", "\u{1CD41}", "\u{1CD42}", "\u{1CD43}", "\u{1CD44}",
+    "\u{2596}", "\u{1CD45}", "\u{1CD46}", "\u{1CD47}", "\u{1CD48}", "\u{258C}", "\u{1CD49}", "\u{1CD4A}",
+    "\u{1CD4B}", "\u{1CD4C}", "\u{259E}", "\u{1CD4D}", "\u{1CD4E}", "\u{1CD4F}", "\u{1CD50}", "\u{259B}",
+    "\u{1CD51}", "\u{1CD52}", "\u{1CD53}", "\u{1CD54}", "\u{1CD55}", "\u{1CD56}", "\u{1CD57}", "\u{1CD58}",
+    "\u{1CD59}", "\u{1CD5A}", "\u{1CD5B}", "\u{1CD5C}", "\u{1CD5D}", "\u{1CD5E}", "\u{1CD5F}", "\u{1CD60}",
+    "\u{1CD61}", "\u{1CD62}", "\u{1CD63}", "\u{1CD64}", "\u{1CD65}", "\u{1CD66}", "\u{1CD67}", "\u{1CD68}",
+    "\u{1CD69}", "\u{1CD6A}", "\u{1CD6B}", "\u{1CD6C}", "\u{1CD6D}", "\u{1CD6E}", "\u{1CD6F}", "\u{1CD70}",
+    "", "\u{1CD71}", "\u{1CD72}", "\u{1CD73}", "\u{1CD74}", "\u{1CD75}", "\u{1CD76}", "\u{1CD77}",
+    "\u{1CD78}", "\u{1CD79}", "\u{1CD7A}", "\u{1CD7B}", "\u{1CD7C}", "\u{1CD7D}", "\u{1CD7E}", "\u{1CD7F}",
+    "\u{1CD80}", "\u{1CD81}", "\u{1CD82}", "\u{1CD83}", "\u{1CD84}", "\u{1CD85}", "\u{1CD86}", "\u{1CD87}",
+    "\u{1CD88}", "\u{1CD89}", "\u{1CD8A}", "\u{1CD8B}", "\u{1CD8C}", "\u{1CD8D}", "\u{1CD8E}", "\u{1CD8F}",
+    "\u{2597}", "\u{1CD90}", "\u{1CD91}", "\u{1CD92}", "\u{1CD93}", "\u{259A}", "\u{1CD94}", "\u{1CD95}",
+    "\u{1CD96}", "\u{1CD97}", "\u{2590}", "\u{1CD98}", "\u{1CD99}", "\u{1CD9A}", "\u{1CD9B}", "\u{259C}",
+    "\u{1CD9C}", "\u{1CD9D}", "\u{1CD9E}", "\u{1CD9F}", "\u{1CDA0}", "\u{1CDA1}", "\u{1CDA2}", "\u{1CDA3}",
+    "\u{1CDA4}", "\u{1CDA5}", "\u{1CDA6}", "\u{1CDA7}", "\u{1CDA8}", "\u{1CDA9}", "\u{1CDAA}", "\u{1CDAB}",
+    "\u{2582}", "\u{1CDAC}", "\u{1CDAD}", "\u{1CDAE}", "\u{1CDAF}", "\u{1CDB0}", "\u{1CDB1}", "\u{1CDB2}",
+    "\u{1CDB3}", "\u{1CDB4}", "\u{1CDB5}", "\u{1CDB6}", "\u{1CDB7}", "\u{1CDB8}", "\u{1CDB9}", "\u{1CDBA}",
+    "\u{1CDBB}", "\u{1CDBC}", "\u{1CDBD}", "\u{1CDBE}", "\u{1CDBF}", "\u{1CDC0}", "\u{1CDC1}", "\u{1CDC2}",
+    "\u{1CDC3}", "\u{1CDC4}", "\u{1CDC5}", "\u{1CDC6}", "\u{1CDC7}", "\u{1CDC8}", "\u{1CDC9}", "\u{1CDCA}",
+    "\u{1CDCB}", "\u{1CDCC}", "\u{1CDCD}", "\u{1CDCE}", "\u{1CDCF}", "\u{1CDD0}", "\u{1CDD1}", "\u{1CDD2}",
+    "\u{1CDD3}", "\u{1CDD4}", "\u{1CDD5}", "\u{1CDD6}", "\u{1CDD7}", "\u{1CDD8}", "\u{1CDD9}", "\u{1CDDA}",
+    "\u{2584}", "\u{1CDDB}", "\u{1CDDC}", "\u{1CDDD}", "\u{1CDDE}", "\u{2599}", "\u{1CDDF}", "\u{1CDE0}",
+    "\u{1CDE1}", "\u{1CDE2}", "\u{259F}", "\u{1CDE3}", "\u{2586}", "\u{1CDE4}", "\u{1CDE5}", "\u{2588}",
+];
+
+// braille patterns in mask order, U+2800 onwards, 3 bytes each
+const BRAILLE: &str = "\
+    ⠀⠁⠂⠃⠄⠅⠆⠇⠈⠉⠊⠋⠌⠍⠎⠏⠐⠑⠒⠓⠔⠕⠖⠗⠘⠙⠚⠛⠜⠝⠞⠟\
+    ⠠⠡⠢⠣⠤⠥⠦⠧⠨⠩⠪⠫⠬⠭⠮⠯⠰⠱⠲⠳⠴⠵⠶⠷⠸⠹⠺⠻⠼⠽⠾⠿\
+    ⡀⡁⡂⡃⡄⡅⡆⡇⡈⡉⡊⡋⡌⡍⡎⡏⡐⡑⡒⡓⡔⡕⡖⡗⡘⡙⡚⡛⡜⡝⡞⡟\
+    ⡠⡡⡢⡣⡤⡥⡦⡧⡨⡩⡪⡫⡬⡭⡮⡯⡰⡱⡲⡳⡴⡵⡶⡷⡸⡹⡺⡻⡼⡽⡾⡿\
+    ⢀⢁⢂⢃⢄⢅⢆⢇⢈⢉⢊⢋⢌⢍⢎⢏⢐⢑⢒⢓⢔⢕⢖⢗⢘⢙⢚⢛⢜⢝⢞⢟\
+    ⢠⢡⢢⢣⢤⢥⢦⢧⢨⢩⢪⢫⢬⢭⢮⢯⢰⢱⢲⢳⢴⢵⢶⢷⢸⢹⢺⢻⢼⢽⢾⢿\
+    ⣀⣁⣂⣃⣄⣅⣆⣇⣈⣉⣊⣋⣌⣍⣎⣏⣐⣑⣒⣓⣔⣕⣖⣗⣘⣙⣚⣛⣜⣝⣞⣟\
+    ⣠⣡⣢⣣⣤⣥⣦⣧⣨⣩⣪⣫⣬⣭⣮⣯⣰⣱⣲⣳⣴⣵⣶⣷⣸⣹⣺⣻⣼⣽⣾⣿";
+
+// braille dot bits by row and column, dots 1-3 and 4-6 run down the columns with 7 and 8 below
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0, 3], [1, 4], [2, 5], [6, 7]];
+
+// printable ascii from space to ~, --ramp characters are sliced out of it
+const ASCII: &str = " !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+
+// ░ light shade, ▒ medium shade, ▓ dark shade and how much of the fg colour each shows
+pub const SHADES: [(&str, f32); 3] = [("\u{2591}", 0.25), ("\u{2592}", 0.5), ("\u{2593}", 0.75)];
+
+// palette entries nearest the cell colour that are tried as fg/bg pairs by the shade and shape fits
+pub(crate) const FIT_CANDIDATES: usize = 8;
 
 #[derive(Debug, Clone)]
 pub struct AnsiImage {
     pub image: PhotonImage,
     pub bitmap: Vec<Vec<u32>>,
+    pub matched: Vec<Vec<u32>>,
+    pub indices: Vec<Vec<u8>>,
     pub halfblock: Vec<Vec<AnsiPixelPair>>,
+    pub palette: Palette,
+    pub truecolor: bool,
+    pub dithered: bool,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -51,30 +111,11 @@ pub struct AnsiPixelPair {
 #[derive(Debug, Clone, Copy)]
 pub struct AnsiPixel {
     pub orig: u32,
-    pub ansi: u8,
-    pub ansi232: u8,
-    pub irc: u8,
-    pub irc88: u8,
-}
-
-impl AnsiPixel {
-    pub fn new(pixel: &u32) -> AnsiPixel {
-        let irc = nearest_hex_color(*pixel, RGB99.to_vec());
-        let irc88 = nearest_hex_color(*pixel, RGB88.to_vec());
-        let ansi = nearest_hex_color(*pixel, ANSI256.to_vec());
-        let ansi232 = nearest_hex_color(*pixel, ANSI232.to_vec());
-        AnsiPixel {
-            orig: *pixel,
-            ansi: ansi,
-            ansi232: ansi232,
-            irc: irc,
-            irc88: irc88,
-        }
-    }
+    pub index: u8,
 }
 
 impl AnsiImage {
-    pub fn new(image: PhotonImage) -> AnsiImage {
+    pub fn new(image: PhotonImage, palette: &Palette, args: &args::Args) -> AnsiImage {
         let mut bitmap = image.get_raw_pixels()
             .chunks(4)
             .map(|x| make_rgb_u32(x.to_vec()))
@@ -83,43 +124,128 @@ impl AnsiImage {
             .map(|x| x.to_vec())
             .collect::<Vec<Vec<u32>>>();
 
+        let mut palette = palette.clone();
+        if let Some(max_colors) = args.max_colors {
+            palette.limit_colors(&bitmap, max_colors as usize);
+        }
+
+        // two colour art, the palette is cut down to the entries nearest the chosen colours
+        if args.mono {
+            mono::threshold_bitmap(&mut bitmap, args.threshold, args.mono_fg, args.mono_bg);
+            palette.keep(&[palette.nearest(args.mono_fg), palette.nearest(args.mono_bg)]);
+        }
+
         if bitmap.len() % 2 != 0 {
             bitmap.push(vec![0; image.get_width() as usize]);
         }
 
-        let halfblock = halfblock_bitmap(&bitmap);
- 
-        return AnsiImage {
-            image: image,
-            bitmap: bitmap,
-            halfblock: halfblock,
+        // the 16 colour palettes are too small to map pixels independently, so dither them by default
+        let method = match (args.palette_dither, args.irc16 || args.ansi16) {
+            _ if args.nodither => None,
+            (Some(method), _) => Some(method),
+            (None, true) => Some(DitherMethod::FloydSteinberg),
+            (None, false) => None,
+        };
+
+        // match grays by luma, or pull out of gamut colours toward the palette, orig keeps the real colour
+        let matched = match (args.grayscale_palette, args.gamut_map) {
+            (true, _) => bitmap
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|&x| {
+                            let y = luma(x).round() as u32;
+                            (y << 16) + (y << 8) + y
+                        })
+                        .collect()
+                })
+                .collect(),
+            (_, true) => gamut::map_bitmap(&bitmap, &palette),
+            _ => bitmap.clone(),
+        };
+
+        let indices = match method {
+            Some(method) => dither_hex_colors(&matched, &palette, method, args.dither_strength),
+            None => map_hex_colors(&matched, &palette),
+        };
+
+        let halfblock = halfblock_bitmap(&bitmap, &indices);
+
+        AnsiImage {
+            image,
+            bitmap,
+            matched,
+            indices,
+            halfblock,
+            palette,
+            truecolor: args.truecolor(),
+            dithered: method.is_some(),
+        }
+    }
+
+    // the colour glyph fitting aims for, exact for truecolor, the dithered palette entry when dithering
+    pub fn target(&self, y: usize, x: usize) -> u32 {
+        match (self.truecolor, self.dithered) {
+            (true, _) => self.bitmap[y][x],
+            (false, true) => self.palette.colors[self.indices[y][x] as usize],
+            (false, false) => self.matched[y][x],
         }
     }
 }
 
+// every pixel is one or two spaces in its bg colour, fg is set to match so irc codes stay valid
+pub fn space_cells(image: &AnsiImage, width: u8) -> Vec<Vec<AnsiCell>> {
+    let char = match width {
+        1 => " ",
+        _ => "  ",
+    };
+
+    image.bitmap[..image.image.get_height() as usize]
+        .iter()
+        .zip(image.indices.iter())
+        .map(|(row, indices)| {
+            row.iter()
+                .zip(indices.iter())
+                .map(|(&orig, &index)| {
+                    let orig = match image.truecolor {
+                        true => orig,
+                        false => image.palette.colors[index as usize],
+                    };
+                    let pixel = AnsiPixel { orig, index };
+
+                    AnsiCell {
+                        char,
+                        fg: pixel,
+                        bg: Some(pixel),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
 pub fn make_rgb_u8(rgb: u32) -> [u8; 3] {
     let r = (rgb >> 16) as u8;
     let g = (rgb >> 8) as u8;
     let b = rgb as u8;
 
-    return [r, g, b]
+    [r, g, b]
 }
 
 pub fn make_rgb_u32(rgb: Vec<u8>) -> u32 {
-    let r = *rgb.get(0).unwrap() as u32;
+    let r = *rgb.first().unwrap() as u32;
     let g = *rgb.get(1).unwrap() as u32;
     let b = *rgb.get(2).unwrap() as u32;
 
-    let rgb = (r << 16) + (g << 8) + b;
-
-    return rgb
+    (r << 16) + (g << 8) + b
 }
 
-pub fn halfblock_bitmap(bitmap: &Vec<Vec<u32>>) -> Vec<Vec<AnsiPixelPair>> {
+pub fn halfblock_bitmap(bitmap: &[Vec<u32>], indices: &[Vec<u8>]) -> Vec<Vec<AnsiPixelPair>> {
     let ansi_bitmap = bitmap
     .iter()
-    .map(|x| {
-       x.iter().map(|y| AnsiPixel::new(y)).collect::<Vec<AnsiPixel>>() 
+    .zip(indices.iter())
+    .map(|(x, i)| {
+       x.iter().zip(i.iter()).map(|(&orig, &index)| AnsiPixel { orig, index }).collect::<Vec<AnsiPixel>>()
     })
     .collect::<Vec<Vec<AnsiPixel>>>();
 
@@ -127,14 +253,14 @@ pub fn halfblock_bitmap(bitmap: &Vec<Vec<u32>>) -> Vec<Vec<AnsiPixelPair>> {
 
     for two_rows in ansi_bitmap.chunks(2) {
         let rows = two_rows.to_vec();
-        let top_row = rows.get(0).unwrap();
+        let top_row = rows.first().unwrap();
         let bottom_row = rows.get(1).unwrap();
 
         let mut ansi_row: Vec<AnsiPixelPair> = Vec::new();
 
-        for i in 0..bitmap.get(0).unwrap().len() {
-            let top_pixel = top_row.get(i as usize).unwrap();
-            let bottom_pixel = bottom_row.get(i as usize).unwrap();
+        for i in 0..bitmap.first().unwrap().len() {
+            let top_pixel = top_row.get(i).unwrap();
+            let bottom_pixel = bottom_row.get(i).unwrap();
 
             let pixel_pair = AnsiPixelPair {
                 top: *top_pixel,
@@ -150,231 +276,692 @@ pub fn halfblock_bitmap(bitmap: &Vec<Vec<u32>>) -> Vec<Vec<AnsiPixelPair>> {
     ansi_canvas
 }
 
-fn get_qb_char(pixel_pairs: &[AnsiPixelPair]) -> &str {
-    let (pair0_top, pair0_bottom) = (&pixel_pairs[0].top.irc, &pixel_pairs[0].bottom.irc);
-    let (pair1_top, pair1_bottom) = (&pixel_pairs[1].top.irc, &pixel_pairs[1].bottom.irc);
-
-    let ups_equal = pair0_top == pair1_top;
-    let downs_equal = pair0_bottom == pair1_bottom;
-    let lefts_equal = pair0_top == pair0_bottom;
-    let rights_equal = pair1_top == pair1_bottom;
-    let left_diag = pair0_top == pair1_bottom;
-    let right_diag = pair1_top == pair0_bottom;
-
-    match (ups_equal, downs_equal, lefts_equal, rights_equal, left_diag, right_diag) {
-        (true, _, true, true, _, _) => FULL,
-        (true, _, true, _, _, _) => UP_LEFT,
-        (true, _, _, true, _, _) => UP_RIGHT,
-        (true, _, _, _, _, _) => UP,
-        (_, true, true, _, _, _) => DOWN_LEFT,
-        (_, true, _, true, _, _) => DOWN_RIGHT,
-        (_, true, _, _, _, _) => DOWN,
-        (_, _, true, false, _, _) => LEFT,
-        (_, _, false, true, _, _) => RIGHT,
-        (_, _, _, _, true, _) => DIAG_LEFT,
-        (_, _, _, _, _, true) => DIAG_RIGHT,
-        _ => UP,
-    }
+// a character cell, for palette render types only the index of fg and bg is used, no bg leaves the terminal's own
+#[derive(Debug, Clone, Copy)]
+pub struct AnsiCell {
+    pub char: &'static str,
+    pub fg: AnsiPixel,
+    pub bg: Option<AnsiPixel>,
 }
 
-pub fn ansi_draw_24bit(image: AnsiImage) -> String {
-    let mut out: String = String::new();
-    for (y, row) in image.halfblock.iter().enumerate() {
-        for pixel_pair in row.iter() {
-            let fg = make_rgb_u8(pixel_pair.top.orig)
-                .to_vec()
-                .iter()
-                .map(|x| x.to_string())
-                .collect::<Vec<String>>();
-            
-            let bg = make_rgb_u8(pixel_pair.bottom.orig)
-                .to_vec()
+pub fn halfblock_cells(image: &AnsiImage) -> Vec<Vec<AnsiCell>> {
+    image.halfblock
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|pixel_pair| AnsiCell {
+                    char: UP,
+                    fg: pixel_pair.top,
+                    bg: Some(pixel_pair.bottom),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// two pixels side by side per character, for wide fonts and banners
+pub fn vertical_halfblock_cells(image: &AnsiImage) -> Vec<Vec<AnsiCell>> {
+    let pixel = |y: usize, x: usize| {
+        let index = image.indices[y][x];
+        let orig = match image.truecolor {
+            true => image.bitmap[y][x],
+            false => image.palette.colors[index as usize],
+        };
+
+        AnsiPixel { orig, index }
+    };
+
+    cell_positions(image, 2, 1)
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|positions| AnsiCell {
+                    char: LEFT,
+                    fg: pixel(positions[0].0, positions[0].1),
+                    bg: Some(pixel(positions[1].0, positions[1].1)),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn quarterblock_cells(image: &AnsiImage) -> Vec<Vec<AnsiCell>> {
+    let glyphs = QUADRANTS.iter().enumerate().map(|(mask, &char)| (mask as u32, char)).collect::<Vec<_>>();
+
+    block_cells(image, 2, 2, &glyphs)
+}
+
+pub fn sextant_cells(image: &AnsiImage) -> Vec<Vec<AnsiCell>> {
+    let glyphs = SEXTANTS.iter().enumerate().map(|(mask, &char)| (mask as u32, char)).collect::<Vec<_>>();
+
+    block_cells(image, 2, 3, &glyphs)
+}
+
+pub fn octant_cells(image: &AnsiImage) -> Vec<Vec<AnsiCell>> {
+    let glyphs = OCTANTS
+        .iter()
+        .enumerate()
+        .filter(|(_, char)| !char.is_empty())
+        .map(|(mask, &char)| (mask as u32, char))
+        .collect::<Vec<_>>();
+
+    block_cells(image, 2, 4, &glyphs)
+}
+
+// splits the image into cells of width × height sub-pixels, edge cells repeat the last row or column
+pub fn cell_positions(image: &AnsiImage, width: usize, height: usize) -> Vec<Vec<Vec<(usize, usize)>>> {
+    // the padding row added for halfblocks is left out
+    let (rows, columns) = (image.image.get_height() as usize, image.image.get_width() as usize);
+
+    (0..rows.div_ceil(height))
+        .map(|cy| {
+            (0..columns.div_ceil(width))
+                .map(|cx| {
+                    (0..height)
+                        .flat_map(|y| (0..width).map(move |x| (y, x)))
+                        .map(|(y, x)| ((cy * height + y).min(rows - 1), (cx * width + x).min(columns - 1)))
+                        .collect()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn block_cells(image: &AnsiImage, width: usize, height: usize, glyphs: &[(u32, &'static str)]) -> Vec<Vec<AnsiCell>> {
+    cell_positions(image, width, height)
+        .iter()
+        .map(|row| row.iter().map(|positions| fit_cell(image, positions, glyphs)).collect())
+        .collect()
+}
+
+// dots are lit where the pixel is brighter than the threshold, or dithered between black and white
+pub fn braille_cells(image: &AnsiImage, args: &args::Args) -> Vec<Vec<AnsiCell>> {
+    let bitmap = &image.bitmap[..image.image.get_height() as usize];
+
+    let dots = match args.palette_dither {
+        Some(method) if !args.nodither => {
+            dither_hex_colors(bitmap, &Palette::new(&[0x000000, 0xffffff]), method, args.dither_strength)
                 .iter()
-                .map(|x| x.to_string())
-                .collect::<Vec<String>>();
+                .map(|row| row.iter().map(|&x| x == 1).collect())
+                .collect()
+        }
+        _ => {
+            let threshold = args.threshold.value(bitmap) as f32;
+            bitmap.iter().map(|row| row.iter().map(|&x| luma(x) > threshold).collect()).collect::<Vec<Vec<bool>>>()
+        }
+    };
+
+    // the mean of the given pixels, or of the whole cell when there are none
+    let pixel = |positions: &[(usize, usize)], all: &[(usize, usize)]| match positions.is_empty() {
+        true => mean_pixel(image, all),
+        false => mean_pixel(image, positions),
+    };
+
+    cell_positions(image, 2, 4)
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|positions| {
+                    let (mut mask, mut on, mut off) = (0, Vec::new(), Vec::new());
+                    for (i, &(y, x)) in positions.iter().enumerate() {
+                        match dots[y][x] {
+                            true => {
+                                mask |= 1 << BRAILLE_DOTS[i / 2][i % 2];
+                                on.push((y, x));
+                            }
+                            false => off.push((y, x)),
+                        }
+                    }
+
+                    AnsiCell {
+                        char: &BRAILLE[mask * 3..mask * 3 + 3],
+                        fg: pixel(&on, positions),
+                        bg: args.braille_bg.then(|| pixel(&off, positions)),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
 
-            out.push_str(format!("\x1b[38;2;{}m\x1b[48;2;{}m{}", fg.join(";"), bg.join(";"), UP).as_str());
+// maps the luma of each cell onto a character ramp, drawn in the cell colour on the terminal's bg
+pub fn ascii_cells(image: &AnsiImage, ramp: &str) -> Vec<Vec<AnsiCell>> {
+    cell_positions(image, 1, 2)
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|positions| {
+                    let colors = positions.iter().map(|&(y, x)| image.bitmap[y][x]).collect::<Vec<u32>>();
+                    let level = luma(mean_color(&colors).unwrap()) / 255.0 * (ramp.len() - 1) as f32;
+                    let c = ramp.as_bytes()[level.round() as usize] as usize;
+
+                    AnsiCell {
+                        char: &ASCII[c - 32..c - 31],
+                        fg: mean_pixel(image, positions),
+                        bg: None,
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// the mean colour of the pixels, exact for truecolor or the nearest palette entry
+fn mean_pixel(image: &AnsiImage, positions: &[(usize, usize)]) -> AnsiPixel {
+    let mean = mean_color(&positions.iter().map(|&(y, x)| image.target(y, x)).collect::<Vec<u32>>()).unwrap();
+    let index = image.palette.nearest(mean);
+
+    AnsiPixel {
+        orig: if image.truecolor { mean } else { image.palette.colors[index as usize] },
+        index,
+    }
+}
+
+pub fn mean_color(colors: &[u32]) -> Option<u32> {
+    if colors.is_empty() {
+        return None;
+    }
+
+    let mut sum = [0u32; 3];
+    for &color in colors {
+        for (s, x) in sum.iter_mut().zip(make_rgb_u8(color)) {
+            *s += x as u32;
         }
-        out.push_str("\x1b[0m");
+    }
+
+    let n = colors.len() as u32;
+    Some(make_rgb_u32(sum.iter().map(|x| ((x + n / 2) / n) as u8).collect()))
+}
 
-        if y != image.halfblock.len() - 1 {
-            out.push_str("\n");
+// the glyph splitting the pixels into the two groups closest to their own means, (error, glyph, fg, bg)
+fn best_split(colors: &[u32], glyphs: &[(u32, &'static str)]) -> (u32, &'static str, u32, u32) {
+    let mut best = (u32::MAX, glyphs[0].1, colors[0], colors[0]);
+
+    for &(mask, char) in glyphs {
+        let (mut fg, mut bg) = (Vec::new(), Vec::new());
+        for (i, &color) in colors.iter().enumerate() {
+            match mask >> i & 1 {
+                1 => fg.push(color),
+                _ => bg.push(color),
+            }
+        }
+
+        let (fg, bg) = match (mean_color(&fg), mean_color(&bg)) {
+            (Some(fg), Some(bg)) => (fg, bg),
+            (Some(x), None) | (None, Some(x)) => (x, x),
+            (None, None) => continue,
+        };
+
+        let error = colors
+            .iter()
+            .enumerate()
+            .map(|(i, &color)| {
+                let side = if mask >> i & 1 == 1 { fg } else { bg };
+                color_distance_squared(hex_to_rgb(color), hex_to_rgb(side))
+            })
+            .sum::<u32>();
+
+        if error < best.0 {
+            best = (error, char, fg, bg);
         }
     }
-    return out
+
+    best
 }
 
-pub fn ansi_draw_24bit_qb(image: AnsiImage) -> String {
-    let mut out: String = String::new();
-    for (y, row) in image.halfblock.iter().enumerate() {
-        for pixel_pairs in row.chunks(2) {
-            let fg = make_rgb_u8(pixel_pairs[0].top.orig)
-                .to_vec()
-                .iter()
-                .map(|x| x.to_string())
-                .collect::<Vec<String>>();
-            
-            let bg = make_rgb_u8(pixel_pairs[0].bottom.orig)
-                .to_vec()
-                .iter()
-                .map(|x| x.to_string())
-                .collect::<Vec<String>>();
+// the glyph and fg/bg pair that best reproduce the pixels, a set mask bit means the pixel is drawn in fg
+fn fit_cell(image: &AnsiImage, positions: &[(usize, usize)], glyphs: &[(u32, &'static str)]) -> AnsiCell {
+    let palette = &image.palette;
+
+    // exact colours, each side is drawn in its mean
+    if image.truecolor {
+        let colors = positions.iter().map(|&(y, x)| image.bitmap[y][x]).collect::<Vec<u32>>();
+        let (_, char, fg, bg) = best_split(&colors, glyphs);
+        let pixel = |orig: u32| AnsiPixel { orig, index: palette.nearest(orig) };
+
+        return AnsiCell { char, fg: pixel(fg), bg: Some(pixel(bg)) };
+    }
 
-            let char = match y {
-                _ if y == image.halfblock.len() - 1 => UP,
-                _ => get_qb_char(pixel_pairs),
+    // palette entries, tried in pairs from the matched indices and the nearest entries to the best split
+    let colors = positions.iter().map(|&(y, x)| image.target(y, x)).collect::<Vec<u32>>();
+    let (_, _, split_fg, split_bg) = best_split(&colors, glyphs);
+
+    let mut candidates = positions.iter().map(|&(y, x)| image.indices[y][x]).collect::<Vec<u8>>();
+    candidates.extend([palette.nearest(split_fg), palette.nearest(split_bg)]);
+    candidates.sort();
+    candidates.dedup();
+
+    let distances = candidates
+        .iter()
+        .map(|&index| {
+            let entry = hex_to_rgb(palette.colors[index as usize]);
+            colors.iter().map(|&color| color_distance_squared(hex_to_rgb(color), entry)).collect::<Vec<u32>>()
+        })
+        .collect::<Vec<Vec<u32>>>();
+
+    // glyph by mask, when every pixel can take its nearer colour no other glyph does better
+    let mut by_mask = vec![None; 1 << colors.len()];
+    for &(mask, char) in glyphs {
+        by_mask[mask as usize] = Some(char);
+    }
+
+    let (mut best, mut best_error) = ((candidates[0], candidates[0], glyphs[0].1), u32::MAX);
+    for (f, fg) in candidates.iter().enumerate() {
+        for (b, bg) in candidates.iter().enumerate() {
+            let ideal = (0..colors.len()).fold(0, |mask, i| mask | ((distances[f][i] < distances[b][i]) as usize) << i);
+
+            let fits = match by_mask[ideal] {
+                Some(char) => vec![(ideal as u32, char)],
+                None => glyphs.to_vec(),
             };
 
-            out.push_str(format!("\x1b[38;2;{}m\x1b[48;2;{}m{}", fg.join(";"), bg.join(";"), char).as_str());
-        }
-        out.push_str("\x1b[0m");
+            for (mask, char) in fits {
+                let error = (0..colors.len())
+                    .map(|i| if mask >> i & 1 == 1 { distances[f][i] } else { distances[b][i] })
+                    .sum::<u32>();
 
-        if y != image.halfblock.len() - 1 {
-            out.push_str("\n");
+                if error < best_error {
+                    best_error = error;
+                    best = (*fg, *bg, char);
+                }
+            }
         }
     }
-    return out
+
+    let pixel = |index: u8| AnsiPixel {
+        orig: palette.colors[index as usize],
+        index,
+    };
+
+    AnsiCell {
+        char: best.2,
+        fg: pixel(best.0),
+        bg: Some(pixel(best.1)),
+    }
+}
+
+// picks whichever of the halfblock or a shade glyph blending two palette colours is closest
+pub fn shade_cells(image: &AnsiImage) -> Vec<Vec<AnsiCell>> {
+    let palette = &image.palette;
+    let pixel = |index: u8| AnsiPixel {
+        orig: palette.colors[index as usize],
+        index,
+    };
+
+    let distance = |a: u32, b: (u8, u8, u8)| color_distance_squared(hex_to_rgb(a), b);
+
+    image.halfblock
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|pixel_pair| {
+                    let (top, bottom) = (pixel_pair.top, pixel_pair.bottom);
+
+                    let mut best = AnsiCell {
+                        char: UP,
+                        fg: top,
+                        bg: Some(bottom),
+                    };
+                    let mut best_error = distance(top.orig, hex_to_rgb(palette.colors[top.index as usize]))
+                        + distance(bottom.orig, hex_to_rgb(palette.colors[bottom.index as usize]));
+
+                    let (tr, tg, tb) = hex_to_rgb(top.orig);
+                    let (br, bg, bb) = hex_to_rgb(bottom.orig);
+                    let mean = make_rgb_u32(vec![
+                        ((tr as u16 + br as u16) / 2) as u8,
+                        ((tg as u16 + bg as u16) / 2) as u8,
+                        ((tb as u16 + bb as u16) / 2) as u8,
+                    ]);
+
+                    let candidates = palette.nearest_n(mean, FIT_CANDIDATES);
+
+                    for &fg in candidates.iter() {
+                        for &bg in candidates.iter().filter(|&&bg| bg != fg) {
+                            let (fr, fgg, fb) = hex_to_rgb(palette.colors[fg as usize]);
+                            let (br, bgg, bb) = hex_to_rgb(palette.colors[bg as usize]);
+
+                            for (char, coverage) in SHADES {
+                                let blend = |f: u8, b: u8| (f as f32 * coverage + b as f32 * (1.0 - coverage)).round() as u8;
+                                let blended = (blend(fr, br), blend(fgg, bgg), blend(fb, bb));
+                                let error = distance(top.orig, blended) + distance(bottom.orig, blended);
+
+                                if error < best_error {
+                                    best_error = error;
+                                    best = AnsiCell {
+                                        char,
+                                        fg: pixel(fg),
+                                        bg: Some(pixel(bg)),
+                                    };
+                                }
+                            }
+                        }
+                    }
+
+                    best
+                })
+                .collect()
+        })
+        .collect()
 }
 
-pub fn ansi_draw_8bit(image: AnsiImage, args: &args::Args) -> String {
+// keeps the top bits of a channel, spread back over 0-255
+fn quantize_channel(value: u8, bits: u8) -> u8 {
+    let levels = (1u32 << bits) - 1;
+    let level = (value as u32 * levels + 127) / 255;
+
+    (level * 255 / levels) as u8
+}
+
+// spaces show no fg, so the last one is kept and only the bg changes, (blank, fg)
+fn blank_fg<F: Copy, B>(cell: &AnsiCell, fg: F, last: Option<(F, B)>) -> (bool, F) {
+    let blank = cell.char.trim().is_empty() && cell.bg.is_some();
+
+    match (blank, last) {
+        (true, Some((last_fg, _))) => (true, last_fg),
+        _ => (blank, fg),
+    }
+}
+
+pub fn ansi_draw_24bit(cells: &[Vec<AnsiCell>], bits: u8, compact: bool) -> String {
     let mut out: String = String::new();
-    for (y, row) in image.halfblock.iter().enumerate() {
-        for pixel_pair in row.iter() {
-    
-            let fg = match args.nograyscale {
-                true => pixel_pair.top.ansi232,
-                false => pixel_pair.top.ansi,
-            };
+    for (y, row) in cells.iter().enumerate() {
+        let mut last: Option<([u8; 3], Option<[u8; 3]>)> = None;
 
-            let bg = match args.nograyscale {
-                true => pixel_pair.bottom.ansi232,
-                false => pixel_pair.bottom.ansi,
-            };
+        for cell in row.iter() {
+            let fg = make_rgb_u8(cell.fg.orig).map(|x| quantize_channel(x, bits));
+            let bg = cell.bg.map(|bg| make_rgb_u8(bg.orig).map(|x| quantize_channel(x, bits)));
+
+            let (blank, fg) = blank_fg(cell, fg, last);
+
+            let join = |rgb: [u8; 3]| rgb.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(";");
 
-            out.push_str(format!("\x1b[38;5;{}m\x1b[48;5;{}m{}", fg, bg, UP).as_str());
+            match (compact || blank, last, bg) {
+                (true, Some(last), _) if last == (fg, bg) => out.push_str(cell.char),
+                (true, Some((_, last_bg)), _) if last_bg == bg => {
+                    out.push_str(format!("\x1b[38;2;{}m{}", join(fg), cell.char).as_str())
+                }
+                (true, Some((last_fg, _)), Some(bg)) if last_fg == fg => {
+                    out.push_str(format!("\x1b[48;2;{}m{}", join(bg), cell.char).as_str())
+                }
+                (true, _, Some(bg)) => {
+                    out.push_str(format!("\x1b[38;2;{};48;2;{}m{}", join(fg), join(bg), cell.char).as_str())
+                }
+                (false, _, Some(bg)) => {
+                    out.push_str(format!("\x1b[38;2;{}m\x1b[48;2;{}m{}", join(fg), join(bg), cell.char).as_str())
+                }
+                (_, _, None) => out.push_str(format!("\x1b[38;2;{}m{}", join(fg), cell.char).as_str()),
+            }
+
+            last = Some((fg, bg));
         }
         out.push_str("\x1b[0m");
 
-        if y != image.halfblock.len() - 1 {
-            out.push_str("\n");
+        if y != cells.len() - 1 {
+            out.push('\n');
         }
     }
-    return out
+    out
+}
+
+// sgr codes for the 16 colour palette, 30-37/40-47 and the bright 90-97/100-107
+pub fn ansi16_sgr(fg: Option<u8>, bg: Option<u8>) -> String {
+    let fg = fg.map(|fg| match fg {
+        0..=7 => 30 + fg,
+        _ => 90 + fg - 8,
+    });
+
+    let bg = bg.map(|bg| match bg {
+        0..=7 => 40 + bg,
+        _ => 100 + bg - 8,
+    });
+
+    let codes = fg.into_iter().chain(bg).map(|x| x.to_string()).collect::<Vec<String>>();
+    format!("\x1b[{}m", codes.join(";"))
 }
 
-pub fn ansi_draw_8bit_qb(image: AnsiImage, args: &args::Args) -> String {
+pub fn ansi_draw_4bit(cells: &[Vec<AnsiCell>], compact: bool) -> String {
     let mut out: String = String::new();
-    for (y, row) in image.halfblock.iter().enumerate() {
-        for pixel_pairs in row.chunks(2) {
-            let fg = match args.nograyscale {
-                true => pixel_pairs[0].top.ansi232,
-                false => pixel_pairs[0].top.ansi,
-            };
+    for (y, row) in cells.iter().enumerate() {
+        let mut last: Option<(u8, Option<u8>)> = None;
 
-            let bg = match args.nograyscale {
-                true => pixel_pairs[0].bottom.ansi232,
-                false => pixel_pairs[0].bottom.ansi,
-            };
+        for cell in row.iter() {
+            let (fg, bg) = (cell.fg.index, cell.bg.map(|x| x.index));
 
-            let char = match y {
-                _ if y == image.halfblock.len() - 1 => UP,
-                _ => get_qb_char(pixel_pairs),
-            };
+            let (blank, fg) = blank_fg(cell, fg, last);
 
-            out.push_str(format!("\x1b[38;5;{}m\x1b[48;5;{}m{}", fg, bg, char).as_str());
+            match (compact || blank, last, bg) {
+                (true, Some(last), _) if last == (fg, bg) => out.push_str(cell.char),
+                (true, Some((_, last_bg)), _) if last_bg == bg => {
+                    out.push_str(format!("{}{}", ansi16_sgr(Some(fg), None), cell.char).as_str())
+                }
+                (true, Some((last_fg, _)), Some(bg)) if last_fg == fg => {
+                    out.push_str(format!("{}{}", ansi16_sgr(None, Some(bg)), cell.char).as_str())
+                }
+                _ => out.push_str(format!("{}{}", ansi16_sgr(Some(fg), bg), cell.char).as_str()),
+            }
+
+            last = Some((fg, bg));
         }
         out.push_str("\x1b[0m");
 
-        if y != image.halfblock.len() - 1 {
-            out.push_str("\n");
+        if y != cells.len() - 1 {
+            out.push('\n');
         }
     }
-    return out
+    out
 }
 
-pub fn irc_draw(image: AnsiImage, args: &args::Args) -> String {
+pub fn ansi_draw_8bit(cells: &[Vec<AnsiCell>], compact: bool) -> String {
     let mut out: String = String::new();
-    for (y, row) in image.halfblock.iter().enumerate() {
-        let mut last_fg: u8 = 0;
-        let mut last_bg: u8 = 0;
-        for (x, pixel_pair) in row.iter().enumerate() {
-            let fg = match args.nograyscale {
-                true => pixel_pair.top.irc88,
-                false => pixel_pair.top.irc,
-            };
+    for (y, row) in cells.iter().enumerate() {
+        let mut last: Option<(u8, Option<u8>)> = None;
 
-            let bg = match args.nograyscale {
-                true => pixel_pair.bottom.irc88,
-                false => pixel_pair.bottom.irc,
-            };
+        for cell in row.iter() {
+            let (fg, bg) = (cell.fg.index, cell.bg.map(|x| x.index));
+
+            let (blank, fg) = blank_fg(cell, fg, last);
 
-            if x != 0 {
-                if fg == last_fg && bg == last_bg {
-                    out.push_str(&format!("{}", UP));
-                } else if bg == last_bg {
-                    out.push_str(&format!("\x03{}{}", fg, UP));
-                } else {
-                    out.push_str(&format!("\x03{},{}{}", fg, bg, UP));
+            match (compact || blank, last, bg) {
+                (true, Some(last), _) if last == (fg, bg) => out.push_str(cell.char),
+                (true, Some((_, last_bg)), _) if last_bg == bg => {
+                    out.push_str(format!("\x1b[38;5;{}m{}", fg, cell.char).as_str())
+                }
+                (true, Some((last_fg, _)), Some(bg)) if last_fg == fg => {
+                    out.push_str(format!("\x1b[48;5;{}m{}", bg, cell.char).as_str())
                 }
-            } else {
-                out.push_str(&format!("\x03{},{}{}", fg, bg, UP));
+                (true, _, Some(bg)) => out.push_str(format!("\x1b[38;5;{};48;5;{}m{}", fg, bg, cell.char).as_str()),
+                (false, _, Some(bg)) => out.push_str(format!("\x1b[38;5;{}m\x1b[48;5;{}m{}", fg, bg, cell.char).as_str()),
+                (_, _, None) => out.push_str(format!("\x1b[38;5;{}m{}", fg, cell.char).as_str()),
             }
 
-            last_fg = fg;
-            last_bg = bg;
+            last = Some((fg, bg));
         }
+        out.push_str("\x1b[0m");
 
-        out.push_str("\x0f");
-
-        if y != image.halfblock.len() - 1 {
-            out.push_str("\n");
+        if y != cells.len() - 1 {
+            out.push('\n');
         }
     }
-    return out
+    out
 }
 
-pub fn irc_draw_qb(image: AnsiImage, args: &args::Args) -> String {
+pub fn irc_draw(cells: &[Vec<AnsiCell>]) -> String {
     let mut out: String = String::new();
-    for (y, row) in image.halfblock.iter().enumerate() {
-        let mut last_fg: u8 = 0;
-        let mut last_bg: u8 = 0;
-        for (x, pixel_pairs) in row.chunks(2).enumerate() {
-            let fg = match args.nograyscale {
-                true => pixel_pairs[0].top.irc88,
-                false => pixel_pairs[0].top.irc,
+    for (y, row) in cells.iter().enumerate() {
+        let mut last: Option<(u8, Option<u8>)> = None;
+
+        for cell in row.iter() {
+            let (fg, bg) = (cell.fg.index, cell.bg.map(|x| x.index));
+
+            // a digit or comma straight after a colour code would be read as part of it,
+            // two digit codes stop digits and an empty bold toggle stops a comma after a lone fg
+            let guarded = cell.char.starts_with(|c: char| c.is_ascii_digit() || c == ',');
+            let code = |fg: u8, bg: Option<u8>| match (bg, guarded) {
+                (Some(bg), true) => format!("\x03{:02},{:02}", fg, bg),
+                (Some(bg), false) => format!("\x03{},{}", fg, bg),
+                (None, true) if cell.char == "," => format!("\x03{:02}\x02\x02", fg),
+                (None, true) => format!("\x03{:02}", fg),
+                (None, false) => format!("\x03{}", fg),
             };
 
-            let bg = match args.nograyscale {
-                true => pixel_pairs[0].bottom.irc88,
-                false => pixel_pairs[0].bottom.irc,
-            };
+            match (last, bg) {
+                (Some(last), _) if last == (fg, bg) => out.push_str(cell.char),
+                (Some((_, last_bg)), _) if last_bg == bg => out.push_str(&format!("{}{}", code(fg, None), cell.char)),
+                (_, Some(_)) => out.push_str(&format!("{}{}", code(fg, bg), cell.char)),
+                // a bg can only be cleared by resetting
+                (Some(_), None) => out.push_str(&format!("\x0f{}{}", code(fg, None), cell.char)),
+                (None, None) => out.push_str(&format!("{}{}", code(fg, None), cell.char)),
+            }
 
-            let char = match y {
-                _ if y == image.halfblock.len() - 1 => UP,
-                _ => get_qb_char(pixel_pairs),
-            };
+            last = Some((fg, bg));
+        }
+
+        out.push('\x0f');
 
-            if x == 0 {
-                out.push_str(&format!("\x03{},{}{}", fg, bg, char));
-            } else {
-                if fg == last_fg && bg == last_bg {
-                    out.push_str(&format!("{}", char));
-                } else if bg == last_bg {
-                    out.push_str(&format!("\x03{}{}", fg, char));
-                } else {
-                    out.push_str(&format!("\x03{},{}{}", fg, bg, char));
+        if y != cells.len() - 1 {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(char: &'static str, fg: u8, bg: Option<u8>) -> AnsiCell {
+        let pixel = |index: u8| AnsiPixel { orig: 0, index };
+        AnsiCell { char, fg: pixel(fg), bg: bg.map(pixel) }
+    }
+
+    // 5;n or 2;r;g;b after a 38 or 48
+    fn extended_color<'a>(codes: &mut impl Iterator<Item = &'a str>) -> String {
+        let count = match codes.next().unwrap() {
+            "2" => 3,
+            _ => 1,
+        };
+
+        codes.take(count).collect::<Vec<&str>>().join(";")
+    }
+
+    // the char, fg and bg a cell is drawn with, fg is left out under spaces
+    type Drawn = (char, Option<String>, Option<String>);
+
+    fn render(out: &str) -> Vec<Vec<Drawn>> {
+        out.lines()
+            .map(|line| {
+                let (mut fg, mut bg, mut cells) = (None, None, Vec::new());
+                let mut rest = line;
+
+                while let Some(c) = rest.chars().next() {
+                    if let Some(sgr) = rest.strip_prefix("\x1b[") {
+                        let (codes, after) = sgr.split_once('m').unwrap();
+                        let mut codes = codes.split(';');
+
+                        while let Some(code) = codes.next() {
+                            match code.parse::<u8>().unwrap() {
+                                0 => (fg, bg) = (None, None),
+                                38 => fg = Some(extended_color(&mut codes)),
+                                48 => bg = Some(extended_color(&mut codes)),
+                                x @ (30..=37 | 90..=97) => fg = Some(x.to_string()),
+                                x => bg = Some(x.to_string()),
+                            }
+                        }
+
+                        rest = after;
+                        continue;
+                    }
+
+                    cells.push((c, if c == ' ' { None } else { fg.clone() }, bg.clone()));
+                    rest = &rest[c.len_utf8()..];
                 }
-            }
 
-            last_fg = fg;
-            last_bg = bg;
+                cells
+            })
+            .collect()
+    }
+
+    // rows with runs of repeated colours and blank cells
+    fn sample_cells() -> Vec<Vec<AnsiCell>> {
+        let mut seed = 7u32;
+        let mut next = |n: u32| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) % n
+        };
+
+        (0..4)
+            .map(|_| {
+                (0..40)
+                    .map(|_| {
+                        let char = [UP, UP, " ", "\u{2584}"][next(4) as usize];
+                        let mut cell = cell(char, next(3) as u8 * 5, Some(next(3) as u8 * 3));
+                        cell.fg.orig = cell.fg.index as u32 * 0x0a1408;
+                        cell.bg.as_mut().unwrap().orig = cell.bg.unwrap().index as u32 * 0x140a08;
+                        cell
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compact_renders_the_same_cells() {
+        let cells = sample_cells();
+
+        for (full, compact) in [
+            (ansi_draw_8bit(&cells, false), ansi_draw_8bit(&cells, true)),
+            (ansi_draw_4bit(&cells, false), ansi_draw_4bit(&cells, true)),
+            (ansi_draw_24bit(&cells, 8, false), ansi_draw_24bit(&cells, 8, true)),
+            (ansi_draw_24bit(&cells, 3, false), ansi_draw_24bit(&cells, 3, true)),
+        ] {
+            assert!(compact.len() < full.len());
+            assert!(render(&full).iter().all(|row| row.len() == 40));
+            assert_eq!(render(&compact), render(&full));
         }
+    }
 
-        out.push_str("\x0f");
+    #[test]
+    fn quantize_channel_levels() {
+        assert!((0..=255).all(|x| quantize_channel(x, 8) == x));
 
-        if y != image.halfblock.len() - 1 {
-            out.push_str("\n");
+        for bits in 1..=7 {
+            let levels = (0..=255).map(|x| quantize_channel(x, bits)).collect::<Vec<u8>>();
+            let mut distinct = levels.clone();
+            distinct.dedup();
+
+            assert_eq!(distinct.len(), 1 << bits);
+            assert_eq!((levels[0], levels[255]), (0, 255));
+            assert!(levels.windows(2).all(|x| x[0] <= x[1]));
+            assert!(levels.iter().all(|&x| quantize_channel(x, bits) == x));
         }
     }
-    return out
-}
\ No newline at end of file
+
+    #[test]
+    fn ansi16_compact_emits_only_the_changed_colour() {
+        let cells = vec![vec![cell(UP, 1, Some(4)), cell(UP, 9, Some(4)), cell(UP, 9, Some(0)), cell(UP, 9, Some(0))]];
+        assert_eq!(ansi_draw_4bit(&cells, true), "\x1b[31;44m\u{2580}\x1b[91m\u{2580}\x1b[40m\u{2580}\u{2580}\x1b[0m");
+    }
+
+    #[test]
+    fn irc_digit_after_colour_change() {
+        // fg only, then fg and bg
+        let cells = vec![vec![cell(UP, 4, Some(1)), cell("5", 9, Some(1)), cell("7", 3, Some(2))]];
+        assert_eq!(irc_draw(&cells), "\x034,1\u{2580}\x03095\x0303,027\x0f");
+    }
+
+    #[test]
+    fn irc_comma_after_colour_change() {
+        let cells = vec![vec![cell(UP, 4, Some(1)), cell(",", 9, Some(1)), cell(",", 3, Some(2))]];
+        assert_eq!(irc_draw(&cells), "\x034,1\u{2580}\x0309\x02\x02,\x0303,02,\x0f");
+
+        // no bg at all
+        let cells = vec![vec![cell(",", 12, None), cell("1", 4, None)]];
+        assert_eq!(irc_draw(&cells), "\x0312\x02\x02,\x03041\x0f");
+    }
+
+    #[test]
+    fn irc_digit_without_colour_change() {
+        let cells = vec![vec![cell("1", 4, Some(1)), cell("2", 4, Some(1)), cell(",", 4, Some(1))]];
+        assert_eq!(irc_draw(&cells), "\x0304,0112,\x0f");
+    }
+}
@@ -1,4 +1,5 @@
-use crate::args;
+use crate::{args, lut, tone};
+use crate::palette::{hex_to_rgb, luma, parse_hex_color};
 use photon_rs::{colour_spaces};
 use photon_rs::{channels, conv, effects, filters, monochrome, noise};
 use photon_rs::transform::{resize, SamplingFilter};
@@ -9,19 +10,15 @@ pub fn apply_effects(
     mut photon_image: PhotonImage,
 ) -> PhotonImage {
 
-    // Resize to width
-    let height =
-        (args.width as f32 / photon_image.get_width() as f32 * photon_image.get_height() as f32) as u32;
+    // Resize to width, characters are as tall as the cell aspect says
+    let (columns, rows) = args.cell_size();
+    let height = (args.width as f32 / photon_image.get_width() as f32 * photon_image.get_height() as f32 / args.cell_aspect()
+        * rows as f32
+        * args.cell_chars() as f32) as u32;
 
-    let width = match args.qb {
-        true => args.width * 2,
-        _ => args.width,
-    };
+    let width = args.width * columns;
 
-    photon_image = match args.qb {
-        true => resize(&photon_image, width, height, SamplingFilter::Lanczos3),
-        _ => resize(&mut photon_image, width, height, SamplingFilter::Lanczos3),
-    };
+    photon_image = resize(&photon_image, width, height.max(1), SamplingFilter::Lanczos3);
 
     // Adjust brightness
     match args.brightness {
@@ -61,6 +58,31 @@ pub fn apply_effects(
         colour_spaces::gamma_correction(&mut photon_image, gamma_value, gamma_value, gamma_value);
     }
 
+    // Adjust levels
+    if let Some(levels) = &args.levels {
+        photon_image = tone::levels(&photon_image, levels);
+    }
+
+    // Adjust curves
+    let channels = [&args.curve_red, &args.curve_green, &args.curve_blue].map(|x| x.as_ref());
+    if channels.iter().any(|x| x.is_some()) {
+        photon_image = tone::curves(&photon_image, channels);
+    }
+
+    if let Some(curve) = &args.curve {
+        photon_image = tone::curves(&photon_image, [Some(curve); 3]);
+    }
+
+    // Adjust color balance
+    if args.shadows.is_some() || args.midtones.is_some() || args.highlights.is_some() {
+        photon_image = tone::color_balance(
+            &photon_image,
+            args.shadows.as_ref(),
+            args.midtones.as_ref(),
+            args.highlights.as_ref(),
+        );
+    }
+
     // Adjust dither
     if args.dither > 0 {
         effects::dither(&mut photon_image, args.dither);
@@ -103,7 +125,7 @@ pub fn apply_effects(
 
     // Adjust noise
     if args.noise {
-        noise::add_noise_rand(photon_image.clone());
+        noise::add_noise_rand(&mut photon_image);
     }
 
     // Adjust sharpen
@@ -197,22 +219,65 @@ pub fn apply_effects(
     }
 
     // Adjust oil
-    match &args.oil {
-        Some(oil) => {
-            // split oil at comma
-            let vals: Vec<&str> = oil.split(",").collect();
-
-            // check if args.oil has 2 values
-            if vals.len() == 2 {
-                // convert oil values to i32 and f64
-                let radius: i32 = vals.get(0).unwrap().parse::<i32>().unwrap();
-                let intensity: f64 = vals.get(1).unwrap().parse::<f64>().unwrap();
-
-                effects::oil(&mut photon_image, radius, intensity);
-            }
+    if let Some(oil) = &args.oil {
+        // split oil at comma
+        let vals: Vec<&str> = oil.split(",").collect();
+
+        // check if args.oil has 2 values
+        if vals.len() == 2 {
+            // convert oil values to i32 and f64
+            let radius: i32 = vals.first().unwrap().parse::<i32>().unwrap();
+            let intensity: f64 = vals.get(1).unwrap().parse::<f64>().unwrap();
+
+            effects::oil(&mut photon_image, radius, intensity);
         }
-        None => {}
+    }
+
+    // Adjust gradient_map
+    if let Some(gradient) = &args.gradient_map {
+        photon_image = gradient_map(&photon_image, gradient);
+    }
+
+    // Adjust lut
+    if let Some(lut) = &args.lut {
+        photon_image = lut::apply_lut(&photon_image, lut);
     }
 
     photon_image
 }
+
+// evenly spaced colour stops from dark to light
+#[derive(Debug, Clone)]
+pub struct Gradient(pub Vec<u32>);
+
+pub fn parse_gradient(gradient: &str) -> Result<Gradient, String> {
+    let stops = gradient
+        .split(',')
+        .map(parse_hex_color)
+        .collect::<Result<Vec<u32>, String>>()?;
+
+    match stops.len() {
+        0 | 1 => Err("expected at least 2 comma separated colours".to_string()),
+        _ => Ok(Gradient(stops)),
+    }
+}
+
+// replaces every pixel with the gradient colour at its luma, alpha is kept
+fn gradient_map(photon_image: &PhotonImage, gradient: &Gradient) -> PhotonImage {
+    let stops = gradient.0.iter().map(|&x| hex_to_rgb(x)).collect::<Vec<(u8, u8, u8)>>();
+    let segments = (stops.len() - 1) as f32;
+
+    tone::map_pixels(photon_image, |rgb| {
+        let hex = ((rgb[0] as u32) << 16) + ((rgb[1] as u32) << 8) + rgb[2] as u32;
+        let position = luma(hex) / 255.0 * segments;
+
+        let i = (position.floor() as usize).min(stops.len() - 2);
+        let t = position - i as f32;
+        let (from, to) = (stops[i], stops[i + 1]);
+
+        let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        rgb[0] = lerp(from.0, to.0);
+        rgb[1] = lerp(from.1, to.1);
+        rgb[2] = lerp(from.2, to.2);
+    })
+}
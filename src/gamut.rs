@@ -0,0 +1,144 @@
+use crate::palette::{hex_to_rgb, rgb_to_lab, Palette};
+
+// resolution of the hue/lightness grid the palette's reach is measured on
+const HUE_BUCKETS: usize = 36;
+const LIGHTNESS_BUCKETS: usize = 10;
+
+// points sampled along each segment between two palette colours
+const SEGMENT_SAMPLES: usize = 8;
+
+// chroma below this fraction of the palette's reach is left untouched
+const KNEE: f32 = 0.8;
+
+// how far the palette reaches in chroma for each hue and lightness, an approximation of its convex hull
+pub struct GamutMap {
+    max_chroma: Vec<Vec<f32>>,
+}
+
+impl GamutMap {
+    pub fn new(palette: &Palette) -> GamutMap {
+        let labs = palette.colors
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| palette.enabled[*i])
+            .map(|(_, &hex)| rgb_to_lab(hex_to_rgb(hex)))
+            .collect::<Vec<(f32, f32, f32)>>();
+
+        let mut max_chroma = vec![vec![0.0f32; LIGHTNESS_BUCKETS]; HUE_BUCKETS];
+
+        let mut reach = |(l, a, b): (f32, f32, f32)| {
+            let (chroma, hue) = lab_to_lch(a, b);
+            let (h, l) = (hue_bucket(hue), lightness_bucket(l));
+            max_chroma[h][l] = max_chroma[h][l].max(chroma);
+        };
+
+        // every point on a segment between two palette colours is inside the hull
+        for (i, c1) in labs.iter().enumerate() {
+            reach(*c1);
+
+            for c2 in labs.iter().skip(i + 1) {
+                for s in 1..SEGMENT_SAMPLES {
+                    let t = s as f32 / SEGMENT_SAMPLES as f32;
+                    reach((
+                        c1.0 + (c2.0 - c1.0) * t,
+                        c1.1 + (c2.1 - c1.1) * t,
+                        c1.2 + (c2.2 - c1.2) * t,
+                    ));
+                }
+            }
+        }
+
+        GamutMap { max_chroma }
+    }
+
+    // bilinear between the neighbouring buckets, hue wraps around
+    fn reach(&self, hue: f32, lightness: f32) -> f32 {
+        let h = hue / 360.0 * HUE_BUCKETS as f32 - 0.5;
+        let l = (lightness.clamp(0.0, 100.0) / 100.0 * LIGHTNESS_BUCKETS as f32 - 0.5)
+            .clamp(0.0, (LIGHTNESS_BUCKETS - 1) as f32);
+
+        let h0 = h.floor();
+        let (l0, l1) = (l.floor() as usize, (l.floor() as usize + 1).min(LIGHTNESS_BUCKETS - 1));
+        let (th, tl) = (h - h0, l - l.floor());
+        let h0 = (h0 as i32).rem_euclid(HUE_BUCKETS as i32) as usize;
+        let h1 = (h0 + 1) % HUE_BUCKETS;
+
+        let at = |h: usize| self.max_chroma[h][l0] * (1.0 - tl) + self.max_chroma[h][l1] * tl;
+
+        at(h0) * (1.0 - th) + at(h1) * th
+    }
+
+    // compresses chroma into the palette's reach keeping hue and lightness
+    pub fn map(&self, color: u32) -> u32 {
+        let (l, a, b) = rgb_to_lab(hex_to_rgb(color));
+        let (chroma, hue) = lab_to_lch(a, b);
+        let reach = self.reach(hue, l);
+        let knee = reach * KNEE;
+
+        if chroma <= knee {
+            return color;
+        }
+
+        let mapped = match reach - knee {
+            span if span > 0.0 => knee + span * (1.0 - (-(chroma - knee) / span).exp()),
+            _ => reach,
+        };
+
+        let scale = mapped / chroma;
+        lab_to_hex(l, a * scale, b * scale)
+    }
+}
+
+pub fn map_bitmap(bitmap: &[Vec<u32>], palette: &Palette) -> Vec<Vec<u32>> {
+    let gamut = GamutMap::new(palette);
+
+    bitmap
+        .iter()
+        .map(|row| row.iter().map(|&x| gamut.map(x)).collect())
+        .collect()
+}
+
+fn lab_to_lch(a: f32, b: f32) -> (f32, f32) {
+    let chroma = (a * a + b * b).sqrt();
+    let hue = b.atan2(a).to_degrees().rem_euclid(360.0);
+
+    (chroma, hue)
+}
+
+fn hue_bucket(hue: f32) -> usize {
+    (hue / 360.0 * HUE_BUCKETS as f32) as usize % HUE_BUCKETS
+}
+
+fn lightness_bucket(lightness: f32) -> usize {
+    ((lightness.clamp(0.0, 100.0) / 100.0 * LIGHTNESS_BUCKETS as f32) as usize).min(LIGHTNESS_BUCKETS - 1)
+}
+
+// cie l*a*b* under d65 back to srgb, clamped
+fn lab_to_hex(l: f32, a: f32, b: f32) -> u32 {
+    let fy = (l + 16.0) / 116.0;
+    let fx = fy + a / 500.0;
+    let fz = fy - b / 200.0;
+
+    let f_inv = |t: f32| match t {
+        _ if t > 0.206893 => t * t * t,
+        _ => (t - 16.0 / 116.0) / 7.787,
+    };
+
+    let x = f_inv(fx) * 0.95047;
+    let y = f_inv(fy);
+    let z = f_inv(fz) * 1.08883;
+
+    let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+    let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+    let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+
+    let gamma = |c: f32| {
+        let c = match c {
+            _ if c <= 0.0031308 => 12.92 * c,
+            _ => 1.055 * c.powf(1.0 / 2.4) - 0.055,
+        };
+        (c.clamp(0.0, 1.0) * 255.0).round() as u32
+    };
+
+    (gamma(r) << 16) + (gamma(g) << 8) + gamma(b)
+}
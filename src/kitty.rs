@@ -0,0 +1,50 @@
+use crate::args::Args;
+use photon_rs::PhotonImage;
+
+// base64 bytes per escape, the most the protocol allows in one chunk
+const CHUNK_SIZE: usize = 4096;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &x)| n | (x as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+
+    out
+}
+
+// the processed image as a png, placed over the cells the text output would take up
+pub fn kitty_draw(image: &PhotonImage, args: &Args) -> String {
+    let (_, rows) = args.cell_size();
+    let columns = args.width * args.cell_chars();
+    let lines = image.get_height().div_ceil(rows);
+
+    let payload = base64(&image.get_bytes());
+    let chunks = payload.as_bytes().chunks(CHUNK_SIZE).collect::<Vec<&[u8]>>();
+
+    let mut out = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i != chunks.len() - 1) as u8;
+
+        // q=2 keeps the terminal from answering on stdin
+        match i {
+            0 => out.push_str(&format!("\x1b_Ga=T,f=100,q=2,c={},r={},m={};", columns, lines, more)),
+            _ => out.push_str(&format!("\x1b_Gm={};", more)),
+        }
+
+        out.push_str(std::str::from_utf8(chunk).unwrap());
+        out.push_str("\x1b\\");
+    }
+
+    out
+}
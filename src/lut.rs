@@ -0,0 +1,203 @@
+use crate::tone::map_pixels;
+use photon_rs::PhotonImage;
+use std::fs;
+
+#[derive(Debug, Clone)]
+pub enum Table {
+    // per channel curves
+    OneD(Vec<[f32; 3]>),
+    // size³ entries, red changing fastest
+    ThreeD(usize, Vec<[f32; 3]>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Lut {
+    pub table: Table,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+}
+
+// loads an adobe/resolve .cube file
+pub fn load_lut(path: &str) -> Result<Lut, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse_cube(&contents).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse_triple(values: &[&str]) -> Option<[f32; 3]> {
+    match values {
+        [r, g, b] => Some([r.parse().ok()?, g.parse().ok()?, b.parse().ok()?]),
+        _ => None,
+    }
+}
+
+fn parse_range(values: &[&str]) -> Option<(f32, f32)> {
+    match values {
+        [min, max] => Some((min.parse().ok()?, max.parse().ok()?)),
+        _ => None,
+    }
+}
+
+fn parse_cube(contents: &str) -> Result<Lut, String> {
+    let (mut size_1d, mut size_3d) = (None, None);
+    let (mut domain_min, mut domain_max) = ([0.0; 3], [1.0; 3]);
+    let mut entries = Vec::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        let invalid = || format!("invalid line {}: '{}'", number + 1, line);
+
+        match words[0] {
+            "TITLE" => {}
+            "LUT_1D_SIZE" => size_1d = Some(words.get(1).and_then(|x| x.parse::<usize>().ok()).ok_or_else(invalid)?),
+            "LUT_3D_SIZE" => size_3d = Some(words.get(1).and_then(|x| x.parse::<usize>().ok()).ok_or_else(invalid)?),
+            "DOMAIN_MIN" => domain_min = parse_triple(&words[1..]).ok_or_else(invalid)?,
+            "DOMAIN_MAX" => domain_max = parse_triple(&words[1..]).ok_or_else(invalid)?,
+            // resolve's form of the domain, one range for every channel
+            "LUT_1D_INPUT_RANGE" | "LUT_3D_INPUT_RANGE" => {
+                let (min, max) = parse_range(&words[1..]).ok_or_else(invalid)?;
+                (domain_min, domain_max) = ([min; 3], [max; 3]);
+            }
+            // other keywords are ignored
+            x if x.starts_with(|c: char| c.is_ascii_alphabetic()) => {}
+            _ => entries.push(parse_triple(&words).ok_or_else(invalid)?),
+        }
+    }
+
+    if (0..3).any(|c| domain_min[c] >= domain_max[c]) {
+        return Err("DOMAIN_MIN must be below DOMAIN_MAX".to_string());
+    }
+
+    let table = match (size_1d, size_3d) {
+        (_, Some(size)) if size >= 2 && size.checked_pow(3) == Some(entries.len()) => Table::ThreeD(size, entries),
+        (Some(size), None) if size >= 2 && entries.len() == size => Table::OneD(entries),
+        (None, None) => return Err("missing LUT_1D_SIZE or LUT_3D_SIZE".to_string()),
+        _ => return Err(format!("wrong number of entries ({})", entries.len())),
+    };
+
+    Ok(Lut { table, domain_min, domain_max })
+}
+
+impl Lut {
+    // rgb from 0 to 1 in, rgb from 0 to 1 out
+    fn lookup(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let mut position = [0.0; 3];
+        for c in 0..3 {
+            position[c] = ((rgb[c] - self.domain_min[c]) / (self.domain_max[c] - self.domain_min[c])).clamp(0.0, 1.0);
+        }
+
+        match &self.table {
+            Table::OneD(entries) => {
+                let mut out = [0.0; 3];
+                for c in 0..3 {
+                    let (i, t) = split(position[c], entries.len());
+                    out[c] = entries[i][c] + (entries[i + 1][c] - entries[i][c]) * t;
+                }
+                out
+            }
+            Table::ThreeD(size, entries) => {
+                let (r, tr) = split(position[0], *size);
+                let (g, tg) = split(position[1], *size);
+                let (b, tb) = split(position[2], *size);
+                let entry = |r: usize, g: usize, b: usize| entries[r + g * size + b * size * size];
+
+                let mut out = [0.0; 3];
+                for (c, x) in out.iter_mut().enumerate() {
+                    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+                    let c00 = lerp(entry(r, g, b)[c], entry(r + 1, g, b)[c], tr);
+                    let c10 = lerp(entry(r, g + 1, b)[c], entry(r + 1, g + 1, b)[c], tr);
+                    let c01 = lerp(entry(r, g, b + 1)[c], entry(r + 1, g, b + 1)[c], tr);
+                    let c11 = lerp(entry(r, g + 1, b + 1)[c], entry(r + 1, g + 1, b + 1)[c], tr);
+
+                    *x = lerp(lerp(c00, c10, tg), lerp(c01, c11, tg), tb);
+                }
+                out
+            }
+        }
+    }
+}
+
+// the lower grid index and the fraction towards the next one
+fn split(position: f32, size: usize) -> (usize, f32) {
+    let x = position * (size - 1) as f32;
+    let i = (x.floor() as usize).min(size - 2);
+
+    (i, x - i as f32)
+}
+
+pub fn apply_lut(photon_image: &PhotonImage, lut: &Lut) -> PhotonImage {
+    map_pixels(photon_image, |rgb| {
+        let out = lut.lookup([rgb[0] as f32 / 255.0, rgb[1] as f32 / 255.0, rgb[2] as f32 / 255.0]);
+
+        for (x, out) in rgb.iter_mut().zip(out) {
+            *x = (out * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY_3D: &str = "TITLE \"identity\"\n# comment\nLUT_3D_SIZE 2\n\
+        0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+
+    fn assert_near(a: [f32; 3], b: [f32; 3]) {
+        assert!((0..3).all(|c| (a[c] - b[c]).abs() < 1e-5), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn identity_3d() {
+        let lut = parse_cube(IDENTITY_3D).unwrap();
+        for rgb in [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0], [0.25, 0.5, 0.75], [0.9, 0.1, 0.3]] {
+            assert_near(lut.lookup(rgb), rgb);
+        }
+    }
+
+    #[test]
+    fn identity_1d() {
+        let lut = parse_cube("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").unwrap();
+        for rgb in [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0], [0.25, 0.5, 0.75]] {
+            assert_near(lut.lookup(rgb), rgb);
+        }
+    }
+
+    #[test]
+    fn domain() {
+        let lut = parse_cube("DOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 2\nLUT_1D_SIZE 2\n0 0 0\n1 1 1\n").unwrap();
+        assert_near(lut.lookup([1.0, 0.5, 0.0]), [0.5, 0.25, 0.0]);
+    }
+
+    #[test]
+    fn input_range() {
+        let lut = parse_cube("LUT_1D_SIZE 2\nLUT_1D_INPUT_RANGE 0 4\n0 0 0\n1 1 1\n").unwrap();
+        assert_near(lut.lookup([1.0, 2.0, 4.0]), [0.25, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn malformed() {
+        let cubes = [
+            "",
+            "0 0 0\n1 1 1\n",
+            "LUT_1D_SIZE 2\n0 0 0\n",
+            "LUT_1D_SIZE 1\n0 0 0\n",
+            "LUT_1D_SIZE x\n0 0 0\n1 1 1\n",
+            "LUT_1D_SIZE 2\n0 0\n1 1 1\n",
+            "LUT_1D_SIZE 2\n0 0 zero\n1 1 1\n",
+            "LUT_3D_SIZE 2\n0 0 0\n1 1 1\n",
+            "LUT_3D_SIZE 99999999999\n0 0 0\n",
+            "LUT_1D_SIZE 2\nLUT_1D_INPUT_RANGE 0\n0 0 0\n1 1 1\n",
+            "LUT_1D_SIZE 2\nLUT_1D_INPUT_RANGE 1 0\n0 0 0\n1 1 1\n",
+            "DOMAIN_MIN 1 1 1\nDOMAIN_MAX 0 0 0\nLUT_1D_SIZE 2\n0 0 0\n1 1 1\n",
+        ];
+
+        for cube in cubes {
+            assert!(parse_cube(cube).is_err(), "{:?}", cube);
+        }
+    }
+}
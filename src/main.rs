@@ -2,8 +2,17 @@ mod args;
 mod draw;
 mod palette;
 mod effects;
+mod dither;
+mod gamut;
+mod kitty;
+mod mono;
+mod theme;
+mod palette_cmd;
+mod lut;
+mod shapes;
+mod sixel;
+mod tone;
 
-use reqwest;
 use url::Url;
 
 use photon_rs::PhotonImage;
@@ -14,26 +23,75 @@ use std::{error::Error, io::Cursor, process::exit};
 async fn main() {
     let args = args::parse_args();
 
-    match load_image_from_url_or_path(args.image.as_str()).await {
+    if let Some(args::Command::Palette { action }) = &args.command {
+        if let Err(e) = palette_cmd::run(action, &args).await {
+            eprintln!("Error: {}", e);
+            exit(1);
+        }
+        return;
+    }
+
+    match load_image_from_url_or_path(args.image.as_deref().unwrap_or_default()).await {
         Ok(mut image) => {
             image = effects::apply_effects(
                 &args,
                 image,
             );
 
-            let canvas = draw::AnsiImage::new(image);
+            if args.kitty {
+                println!("{}", kitty::kitty_draw(&image, &args));
+                return;
+            }
+
+            let palette = match args.sixel && !(args.irc || args.irc16 || args.ansi || args.ansi16) {
+                true => sixel::registers(&image),
+                false => palette::target_palette(&args),
+            };
+            if palette.is_empty() {
+                eprintln!("Error: every palette colour is excluded");
+                exit(1);
+            }
 
-            match (args.irc, args.ansi, args.ansi24, args.qb) {
-                (true, _, _, true) => println!("{}", draw::irc_draw_qb(canvas, &args).as_str()),
-                (true, _, _, false) => println!("{}", draw::irc_draw(canvas, &args).as_str()),
-                (_, true, _, true) => println!("{}", draw::ansi_draw_8bit_qb(canvas, &args).as_str()),
-                (_, true, _, false) => println!("{}", draw::ansi_draw_8bit(canvas, &args).as_str()),
-                (_, _, true, true) => println!("{}", draw::ansi_draw_24bit_qb(canvas).as_str()),
-                (_, _, true, false) => println!("{}", draw::ansi_draw_24bit(canvas).as_str()),
-                (_, _, _, true) => println!("{}", draw::irc_draw_qb(canvas, &args).as_str()),
-                _ => println!("{}", draw::irc_draw(canvas, &args).as_str()),
-            }            
+            let canvas = draw::AnsiImage::new(image, &palette, &args);
 
+            if args.sixel {
+                println!("{}", sixel::sixel_draw(&canvas));
+                return;
+            }
+
+            let cells = match args.layout() {
+                args::Layout::Halfblock => draw::halfblock_cells(&canvas),
+                args::Layout::VerticalHalfblock => draw::vertical_halfblock_cells(&canvas),
+                args::Layout::Quarterblock => draw::quarterblock_cells(&canvas),
+                args::Layout::Sextant => draw::sextant_cells(&canvas),
+                args::Layout::Octant => draw::octant_cells(&canvas),
+                args::Layout::Braille => draw::braille_cells(&canvas, &args),
+                args::Layout::Ascii => draw::ascii_cells(&canvas, &args.ramp),
+                args::Layout::Shapes => shapes::shape_cells(&canvas, &args.glyphs),
+                args::Layout::Spaces => draw::space_cells(&canvas, args.space_width),
+                args::Layout::Shade => draw::shade_cells(&canvas),
+            };
+
+            let out = encode(&cells, &args, args.compact, args.ansi24_bits);
+            println!("{}", out);
+
+            // only report when the encoder that ran shrinks its output
+            let shrunk = match args.encoding() {
+                args::Encoding::Irc => false,
+                args::Encoding::Ansi8 | args::Encoding::Ansi4 => args.compact,
+                args::Encoding::Ansi24 => args.compact || args.ansi24_bits < 8,
+            };
+
+            if shrunk {
+                let full = encode(&cells, &args, false, 8).len();
+                let saved = full - out.len().min(full);
+                eprintln!(
+                    "{} bytes, {} saved ({:.1}%)",
+                    out.len(),
+                    saved,
+                    saved as f32 / full.max(1) as f32 * 100.0,
+                );
+            }
         }
         Err(e) => {
             eprintln!("Error: {}", e);
@@ -42,7 +100,16 @@ async fn main() {
     }
 }
 
-async fn load_image_from_url_or_path(image: &str) -> Result<PhotonImage, Box<dyn Error>> {
+fn encode(cells: &[Vec<draw::AnsiCell>], args: &args::Args, compact: bool, bits: u8) -> String {
+    match args.encoding() {
+        args::Encoding::Irc => draw::irc_draw(cells),
+        args::Encoding::Ansi8 => draw::ansi_draw_8bit(cells, compact),
+        args::Encoding::Ansi4 => draw::ansi_draw_4bit(cells, compact),
+        args::Encoding::Ansi24 => draw::ansi_draw_24bit(cells, bits, compact),
+    }
+}
+
+pub async fn load_image_from_url_or_path(image: &str) -> Result<PhotonImage, Box<dyn Error>> {
     match Url::parse(image) {
         Ok(url) => {
             let response = reqwest::get(url).await?;
@@ -0,0 +1,73 @@
+use crate::palette::luma;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Threshold {
+    Auto,
+    Value(u8),
+}
+
+impl Threshold {
+    pub fn value(&self, bitmap: &[Vec<u32>]) -> u8 {
+        match self {
+            Threshold::Auto => otsu_threshold(bitmap),
+            Threshold::Value(x) => *x,
+        }
+    }
+}
+
+pub fn parse_threshold(threshold: &str) -> Result<Threshold, String> {
+    match threshold {
+        "auto" => Ok(Threshold::Auto),
+        _ => threshold
+            .parse::<u8>()
+            .map(Threshold::Value)
+            .map_err(|_| "expected auto or a number from 0 to 255".to_string()),
+    }
+}
+
+// otsu's method, the threshold that best separates the luma histogram into two classes
+pub fn otsu_threshold(bitmap: &[Vec<u32>]) -> u8 {
+    let mut histogram = [0u32; 256];
+    for &pixel in bitmap.iter().flatten() {
+        histogram[luma(pixel).round() as usize] += 1;
+    }
+
+    let total = histogram.iter().sum::<u32>() as f64;
+    let sum_all = histogram.iter().enumerate().map(|(i, &x)| i as f64 * x as f64).sum::<f64>();
+
+    let (mut best, mut best_variance) = (0u8, 0.0f64);
+    let (mut weight_bg, mut sum_bg) = (0.0f64, 0.0f64);
+
+    for (i, &count) in histogram.iter().enumerate() {
+        weight_bg += count as f64;
+        sum_bg += i as f64 * count as f64;
+
+        let weight_fg = total - weight_bg;
+        if weight_bg == 0.0 || weight_fg == 0.0 {
+            continue;
+        }
+
+        let mean_bg = sum_bg / weight_bg;
+        let mean_fg = (sum_all - sum_bg) / weight_fg;
+        let variance = weight_bg * weight_fg * (mean_bg - mean_fg).powi(2);
+
+        if variance > best_variance {
+            best_variance = variance;
+            best = i as u8;
+        }
+    }
+
+    best
+}
+
+// pixels brighter than the threshold become fg, the rest bg
+pub fn threshold_bitmap(bitmap: &mut [Vec<u32>], threshold: Threshold, fg: u32, bg: u32) {
+    let threshold = threshold.value(bitmap);
+
+    for pixel in bitmap.iter_mut().flatten() {
+        *pixel = match luma(*pixel) > threshold as f32 {
+            true => fg,
+            false => bg,
+        };
+    }
+}
@@ -1,16 +1,13 @@
-pub const RGB88: [u32; 88] = [
-    0xffffff, 0x000000, 0x00007f, 0x009300, 0xff0000, 0x7f0000, 0x9c009c, 0xfc7f00,
-    0xffff00, 0x00fc00, 0x009393, 0x00ffff, 0x0000fc, 0xff00ff, 0x0,      0x0,
-    0x470000, 0x472100, 0x474700, 0x324700, 0x004700, 0x00472c, 0x004747, 0x002747,
-    0x000047, 0x2e0047, 0x470047, 0x47002a, 0x740000, 0x743a00, 0x747400, 0x517400,
-    0x007400, 0x007449, 0x007474, 0x004074, 0x000074, 0x4b0074, 0x740074, 0x740045,
-    0xb50000, 0xb56300, 0xb5b500, 0x7db500, 0x00b500, 0x00b571, 0x00b5b5, 0x0063b5,
-    0x0000b5, 0x7500b5, 0xb500b5, 0xb5006b, 0xff0000, 0xff8c00, 0xffff00, 0xb2ff00,
-    0x00ff00, 0x00ffa0, 0x00ffff, 0x008cff, 0x0000ff, 0xa500ff, 0xff00ff, 0xff0098,
-    0xff5959, 0xffb459, 0xffff71, 0xcfff60, 0x6fff6f, 0x65ffc9, 0x6dffff, 0x59b4ff,
-    0x5959ff, 0xc459ff, 0xff66ff, 0xff59bc, 0xff9c9c, 0xffd39c, 0xffff9c, 0xe2ff9c,
-    0x9cff9c, 0x9cffdb, 0x9cffff, 0x9cd3ff, 0x9c9cff, 0xdc9cff, 0xff9cff, 0xff94d3,
-];
+use crate::args;
+use crate::draw::make_rgb_u32;
+use photon_rs::transform::{resize, SamplingFilter};
+use photon_rs::PhotonImage;
+
+// the longest side images are scaled down to before picking their dominant colours
+const SAMPLE_SIZE: u32 = 256;
+
+// grayscale entries left out of the irc palette by --nograyscale
+pub const IRC_GRAYSCALE: [u8; 13] = [14, 15, 88, 89, 90, 91, 92, 93, 94, 95, 96, 97, 98];
 
 pub const RGB99: [u32; 99] = [
     0xffffff, 0x000000, 0x00007f, 0x009300, 0xff0000, 0x7f0000, 0x9c009c, 0xfc7f00,
@@ -28,6 +25,26 @@ pub const RGB99: [u32; 99] = [
     0xbcbcbc, 0xe2e2e2, 0xffffff,
 ];
 
+pub const RGB16: [u32; 16] = [
+    0xffffff, 0x000000, 0x00007f, 0x009300, 0xff0000, 0x7f0000, 0x9c009c, 0xfc7f00,
+    0xffff00, 0x00fc00, 0x009393, 0x00ffff, 0x0000fc, 0xff00ff, 0x7f7f7f, 0xd2d2d2,
+];
+
+pub const ANSI16_XTERM: [u32; 16] = [
+    0x000000, 0x800000, 0x008000, 0x808000, 0x000080, 0x800080, 0x008080, 0xc0c0c0,
+    0x808080, 0xff0000, 0x00ff00, 0xffff00, 0x0000ff, 0xff00ff, 0x00ffff, 0xffffff,
+];
+
+pub const ANSI16_VGA: [u32; 16] = [
+    0x000000, 0xaa0000, 0x00aa00, 0xaa5500, 0x0000aa, 0xaa00aa, 0x00aaaa, 0xaaaaaa,
+    0x555555, 0xff5555, 0x55ff55, 0xffff55, 0x5555ff, 0xff55ff, 0x55ffff, 0xffffff,
+];
+
+pub const ANSI16_CAMPBELL: [u32; 16] = [
+    0x0c0c0c, 0xc50f1f, 0x13a10e, 0xc19c00, 0x0037da, 0x881798, 0x3a96dd, 0xcccccc,
+    0x767676, 0xe74856, 0x16c60c, 0xf9f1a5, 0x3b78ff, 0xb4009e, 0x61d6d6, 0xf2f2f2,
+];
+
 pub const ANSI256: [u32; 256] = [
     0x000000, 0x800000, 0x008000, 0x808000, 0x000080, 0x800080, 0x008080, 0xc0c0c0,
     0x808080, 0xff0000, 0x00ff00, 0xffff00, 0x0000ff, 0xff00ff, 0x00ffff, 0xffffff,
@@ -63,39 +80,193 @@ pub const ANSI256: [u32; 256] = [
     0xa8a8a8, 0xb2b2b2, 0xbcbcbc, 0xc6c6c6, 0xd0d0d0, 0xdadada, 0xe4e4e4, 0xeeeeee
 ];
 
-pub const ANSI232: [u32; 232] = [
-    0x000000, 0x800000, 0x008000, 0x808000, 0x000080, 0x800080, 0x008080, 0x0,
-    0x0,      0xff0000, 0x00ff00, 0xffff00, 0x0000ff, 0xff00ff, 0x00ffff, 0x0,
-    0x0,      0x00005f, 0x000087, 0x0000af, 0x0000d7, 0x0000ff, 0x005f00, 0x005f5f,
-    0x005f87, 0x005faf, 0x005fd7, 0x005fff, 0x008700, 0x00875f, 0x008787, 0x0087af,
-    0x0087d7, 0x0087ff, 0x00af00, 0x00af5f, 0x00af87, 0x00afaf, 0x00afd7, 0x00afff,
-    0x00d700, 0x00d75f, 0x00d787, 0x00d7af, 0x00d7d7, 0x00d7ff, 0x00ff00, 0x00ff5f,
-    0x00ff87, 0x00ffaf, 0x00ffd7, 0x00ffff, 0x5f0000, 0x5f005f, 0x5f0087, 0x5f00af,
-    0x5f00d7, 0x5f00ff, 0x5f5f00, 0x0,      0x5f5f87, 0x5f5faf, 0x5f5fd7, 0x5f5fff,
-    0x5f8700, 0x5f875f, 0x5f8787, 0x5f87af, 0x5f87d7, 0x5f87ff, 0x5faf00, 0x5faf5f,
-    0x5faf87, 0x5fafaf, 0x5fafd7, 0x5fafff, 0x5fd700, 0x5fd75f, 0x5fd787, 0x5fd7af,
-    0x5fd7d7, 0x5fd7ff, 0x5fff00, 0x5fff5f, 0x5fff87, 0x5fffaf, 0x5fffd7, 0x5fffff,
-    0x870000, 0x87005f, 0x870087, 0x8700af, 0x8700d7, 0x8700ff, 0x875f00, 0x875f5f,
-    0x875f87, 0x875faf, 0x875fd7, 0x875fff, 0x878700, 0x87875f, 0x0,      0x8787af,
-    0x8787d7, 0x8787ff, 0x87af00, 0x87af5f, 0x87af87, 0x87afaf, 0x87afd7, 0x87afff,
-    0x87d700, 0x87d75f, 0x87d787, 0x87d7af, 0x87d7d7, 0x87d7ff, 0x87ff00, 0x87ff5f,
-    0x87ff87, 0x87ffaf, 0x87ffd7, 0x87ffff, 0xaf0000, 0xaf005f, 0xaf0087, 0xaf00af,
-    0xaf00d7, 0xaf00ff, 0xaf5f00, 0xaf5f5f, 0xaf5f87, 0xaf5faf, 0xaf5fd7, 0xaf5fff,
-    0xaf8700, 0xaf875f, 0xaf8787, 0xaf87af, 0xaf87d7, 0xaf87ff, 0xafaf00, 0xafaf5f,
-    0xafaf87, 0x0,      0xafafd7, 0xafafff, 0xafd700, 0xafd75f, 0xafd787, 0xafd7af,
-    0xafd7d7, 0xafd7ff, 0xafff00, 0xafff5f, 0xafff87, 0xafffaf, 0xafffd7, 0xafffff,
-    0xd70000, 0xd7005f, 0xd70087, 0xd700af, 0xd700d7, 0xd700ff, 0xd75f00, 0xd75f5f,
-    0xd75f87, 0xd75faf, 0xd75fd7, 0xd75fff, 0xd78700, 0xd7875f, 0xd78787, 0xd787af,
-    0xd787d7, 0xd787ff, 0xd7af00, 0xd7af5f, 0xd7af87, 0xd7afaf, 0xd7afd7, 0xd7afff,
-    0xd7d700, 0xd7d75f, 0xd7d787, 0xd7d7af, 0x0,      0xd7d7ff, 0xd7ff00, 0xd7ff5f,
-    0xd7ff87, 0xd7ffaf, 0xd7ffd7, 0xd7ffff, 0xff0000, 0xff005f, 0xff0087, 0xff00af,
-    0xff00d7, 0xff00ff, 0xff5f00, 0xff5f5f, 0xff5f87, 0xff5faf, 0xff5fd7, 0xff5fff,
-    0xff8700, 0xff875f, 0xff8787, 0xff87af, 0xff87d7, 0xff87ff, 0xffaf00, 0xffaf5f,
-    0xffaf87, 0xffafaf, 0xffafd7, 0xffafff, 0xffd700, 0xffd75f, 0xffd787, 0xffd7af,
-    0xffd7d7, 0xffd7ff, 0xffff00, 0xffff5f, 0xffff87, 0xffffaf, 0xffffd7, 0xffffff,
+// black, white and grays of each palette, matched alone by --grayscale-palette
+pub const IRC_GRAYS: [u8; 15] = [0, 1, 14, 15, 88, 89, 90, 91, 92, 93, 94, 95, 96, 97, 98];
+pub const IRC16_GRAYS: [u8; 4] = [0, 1, 14, 15];
+pub const ANSI_GRAYS: [u8; 34] = [
+    0, 7, 8, 15, 16, 59, 102, 145, 188, 231, 232, 233, 234, 235, 236, 237, 238,
+    239, 240, 241, 242, 243, 244, 245, 246, 247, 248, 249, 250, 251, 252, 253, 254, 255,
+];
+pub const ANSI16_GRAYS: [u8; 4] = [0, 7, 8, 15];
+
+// grayscale entries left out of the 8-bit ansi palette by --nograyscale
+pub const ANSI_GRAYSCALE: [u8; 31] = [
+    7, 8, 15, 59, 102, 145, 188, 232, 233, 234, 235, 236, 237, 238, 239, 240,
+    241, 242, 243, 244, 245, 246, 247, 248, 249, 250, 251, 252, 253, 254, 255,
 ];
 
-fn hex_to_rgb(hex: u32) -> (u8, u8, u8) {
+// a target palette, entries that aren't enabled are never matched
+#[derive(Debug, Clone)]
+pub struct Palette {
+    pub colors: Vec<u32>,
+    pub enabled: Vec<bool>,
+}
+
+impl Palette {
+    pub fn new(colors: &[u32]) -> Palette {
+        Palette {
+            colors: colors.to_vec(),
+            enabled: vec![true; colors.len()],
+        }
+    }
+
+    // the n dominant colours of a downscaled copy of the image
+    pub fn from_image(image: &PhotonImage, n: usize) -> Palette {
+        let (width, height) = (image.get_width(), image.get_height());
+        let scale = SAMPLE_SIZE as f32 / width.max(height) as f32;
+        let sample = match scale < 1.0 {
+            true => resize(
+                image,
+                ((width as f32 * scale) as u32).max(1),
+                ((height as f32 * scale) as u32).max(1),
+                SamplingFilter::Triangle,
+            ),
+            false => image.clone(),
+        };
+
+        let bitmap = sample.get_raw_pixels()
+            .chunks(4)
+            .map(|x| make_rgb_u32(x.to_vec()))
+            .collect::<Vec<u32>>()
+            .chunks(sample.get_width() as usize)
+            .map(|x| x.to_vec())
+            .collect::<Vec<Vec<u32>>>();
+
+        Palette::new(&dominant_colors(&bitmap, n))
+    }
+
+    pub fn exclude(&mut self, indices: &[u8]) {
+        for &index in indices {
+            if let Some(enabled) = self.enabled.get_mut(index as usize) {
+                *enabled = false;
+            }
+        }
+    }
+
+    // disables every entry not in the given list
+    pub fn keep(&mut self, indices: &[u8]) {
+        for (i, enabled) in self.enabled.iter_mut().enumerate() {
+            *enabled &= indices.contains(&(i as u8));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.enabled.iter().any(|&x| x)
+    }
+
+    // keeps only the enabled entries closest to the dominant colours of the image
+    pub fn limit_colors(&mut self, bitmap: &[Vec<u32>], max_colors: usize) {
+        let mut chosen = vec![false; self.colors.len()];
+
+        for color in dominant_colors(bitmap, max_colors) {
+            let rgb = hex_to_rgb(color);
+
+            let index = self.colors
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| self.enabled[*i] && !chosen[*i])
+                .min_by_key(|(_, &hex)| color_distance_squared(rgb, hex_to_rgb(hex)))
+                .map(|(i, _)| i);
+
+            if let Some(index) = index {
+                chosen[index] = true;
+            }
+        }
+
+        self.enabled = chosen;
+    }
+
+    // the n enabled entries closest to the colour, closest first
+    pub fn nearest_n(&self, input_color: u32, n: usize) -> Vec<u8> {
+        let rgb = hex_to_rgb(input_color);
+
+        let mut indices = (0..self.colors.len())
+            .filter(|&i| self.enabled[i])
+            .collect::<Vec<usize>>();
+
+        indices.sort_by_key(|&i| color_distance_squared(rgb, hex_to_rgb(self.colors[i])));
+        indices.into_iter().take(n).map(|i| i as u8).collect()
+    }
+
+    pub fn nearest_by(&self, input_color: u32, metric: ColorMetric) -> u8 {
+        let index = (0..self.colors.len())
+            .filter(|&i| self.enabled[i])
+            .min_by(|&a, &b| {
+                metric.distance(input_color, self.colors[a])
+                    .total_cmp(&metric.distance(input_color, self.colors[b]))
+            })
+            .unwrap_or(0);
+
+        index as u8
+    }
+
+    pub fn nearest(&self, input_color: u32) -> u8 {
+        let rgb = hex_to_rgb(input_color);
+
+        let index = self.colors
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.enabled[*i])
+            .min_by_key(|(_, &hex)| color_distance_squared(rgb, hex_to_rgb(hex)))
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+
+        index as u8
+    }
+}
+
+// the palette matched against for the selected render type, --ansi24 only uses it for quarterblock shapes
+pub fn target_palette(args: &args::Args) -> Palette {
+    let (mut palette, grays) = match (args.irc || args.irc16, args.ansi, args.ansi16) {
+        (true, _, _) if args.irc16 => (irc16_palette(), IRC16_GRAYS.to_vec()),
+        (true, _, _) => (irc_palette(args), IRC_GRAYS.to_vec()),
+        (_, true, _) => (ansi_palette(args), ANSI_GRAYS.to_vec()),
+        (_, _, true) => (ansi16_palette(args), ANSI16_GRAYS.to_vec()),
+        _ => (irc_palette(args), IRC_GRAYS.to_vec()),
+    };
+
+    if args.grayscale_palette {
+        palette.keep(&grays);
+    }
+
+    palette.exclude(&args.exclude_colors);
+    palette
+}
+
+pub fn irc_palette(args: &args::Args) -> Palette {
+    let mut palette = Palette::new(&RGB99);
+    if args.nograyscale {
+        palette.exclude(&IRC_GRAYSCALE);
+    }
+    palette
+}
+
+pub fn irc16_palette() -> Palette {
+    Palette::new(&RGB16)
+}
+
+// the 16 basic colours as --term-theme or --ansi16-colors sets them
+pub fn ansi16_palette(args: &args::Args) -> Palette {
+    Palette::new(&args.term_theme.as_ref().unwrap_or(&args.ansi16_colors).0)
+}
+
+pub fn ansi_palette(args: &args::Args) -> Palette {
+    let mut colors = ANSI256.to_vec();
+    if let Some(theme) = &args.term_theme {
+        colors[..16].copy_from_slice(&theme.0);
+    }
+
+    let mut palette = Palette::new(&colors);
+    if args.nograyscale {
+        palette.exclude(&ANSI_GRAYSCALE);
+    }
+    if args.nobasic {
+        palette.exclude(&(0..16).collect::<Vec<u8>>());
+    }
+    palette
+}
+
+pub fn hex_to_rgb(hex: u32) -> (u8, u8, u8) {
     let r = ((hex >> 16) & 0xFF) as u8;
     let g = ((hex >> 8) & 0xFF) as u8;
     let b = (hex & 0xFF) as u8;
@@ -103,7 +274,13 @@ fn hex_to_rgb(hex: u32) -> (u8, u8, u8) {
     (r, g, b)
 }
 
-fn color_distance_squared(c1: (u8, u8, u8), c2: (u8, u8, u8)) -> u32 {
+// rec. 601 luma, 0 to 255
+pub fn luma(hex: u32) -> f32 {
+    let (r, g, b) = hex_to_rgb(hex);
+    0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32
+}
+
+pub fn color_distance_squared(c1: (u8, u8, u8), c2: (u8, u8, u8)) -> u32 {
     let dr = c1.0 as i32 - c2.0 as i32;
     let dg = c1.1 as i32 - c2.1 as i32;
     let db = c1.2 as i32 - c2.2 as i32;
@@ -111,14 +288,240 @@ fn color_distance_squared(c1: (u8, u8, u8), c2: (u8, u8, u8)) -> u32 {
     (dr * dr + dg * dg + db * db) as u32
 }
 
-pub fn nearest_hex_color(input_color: u32, hex_colors: Vec<u32>) -> u8 {
-    let hex = hex_colors
+
+#[derive(Debug, Clone, Copy)]
+pub enum ColorMetric {
+    Rgb,
+    Redmean,
+    Cielab,
+}
+
+impl ColorMetric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorMetric::Rgb => "rgb",
+            ColorMetric::Redmean => "redmean",
+            ColorMetric::Cielab => "cielab",
+        }
+    }
+
+    pub fn distance(&self, c1: u32, c2: u32) -> f32 {
+        let (c1, c2) = (hex_to_rgb(c1), hex_to_rgb(c2));
+
+        match self {
+            ColorMetric::Rgb => color_distance_squared(c1, c2) as f32,
+            ColorMetric::Redmean => {
+                // weights channels by how sensitive the eye is to them at this level of red
+                let rmean = (c1.0 as f32 + c2.0 as f32) / 2.0;
+                let dr = c1.0 as f32 - c2.0 as f32;
+                let dg = c1.1 as f32 - c2.1 as f32;
+                let db = c1.2 as f32 - c2.2 as f32;
+
+                (2.0 + rmean / 256.0) * dr * dr + 4.0 * dg * dg + (2.0 + (255.0 - rmean) / 256.0) * db * db
+            }
+            ColorMetric::Cielab => {
+                let (l1, a1, b1) = rgb_to_lab(c1);
+                let (l2, a2, b2) = rgb_to_lab(c2);
+
+                (l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)
+            }
+        }
+    }
+}
+
+// srgb to cie l*a*b* under d65
+pub fn rgb_to_lab(rgb: (u8, u8, u8)) -> (f32, f32, f32) {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        match c {
+            _ if c <= 0.04045 => c / 12.92,
+            _ => ((c + 0.055) / 1.055).powf(2.4),
+        }
+    };
+
+    let (r, g, b) = (linear(rgb.0), linear(rgb.1), linear(rgb.2));
+
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| match t {
+        _ if t > 0.008856 => t.cbrt(),
+        _ => 7.787 * t + 16.0 / 116.0,
+    };
+
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+pub fn parse_hex_color(color: &str) -> Result<u32, String> {
+    let hex = color.trim().trim_start_matches('#').trim_start_matches("0x");
+
+    if hex.len() != 6 {
+        return Err(format!("invalid colour '{}', expected #RRGGBB", color));
+    }
+
+    u32::from_str_radix(hex, 16).map_err(|_| format!("invalid colour '{}', expected #RRGGBB", color))
+}
+
+// rgb values assumed for the 16 basic ansi colours, a preset name or 16 comma separated colours
+#[derive(Debug, Clone)]
+pub struct Ansi16Colors(pub [u32; 16]);
+
+pub fn parse_ansi16_colors(colors: &str) -> Result<Ansi16Colors, String> {
+    match colors {
+        "xterm" => Ok(Ansi16Colors(ANSI16_XTERM)),
+        "vga" => Ok(Ansi16Colors(ANSI16_VGA)),
+        "campbell" => Ok(Ansi16Colors(ANSI16_CAMPBELL)),
+        _ => {
+            let hex_colors = colors
+                .split(',')
+                .map(parse_hex_color)
+                .collect::<Result<Vec<u32>, String>>()?;
+
+            match hex_colors.try_into() {
+                Ok(hex_colors) => Ok(Ansi16Colors(hex_colors)),
+                Err(_) => Err("expected xterm, vga, campbell or 16 comma separated colours".to_string()),
+            }
+        }
+    }
+}
+
+// median cut refined with a few rounds of k-means, most common colour first
+pub fn dominant_colors(bitmap: &[Vec<u32>], count: usize) -> Vec<u32> {
+    let pixels = bitmap
         .iter()
-        .map(|&hex| (hex, hex_to_rgb(hex)))
-        .min_by_key(|(_, rgb)| color_distance_squared(hex_to_rgb(input_color), *rgb))
-        .map(|(hex, _)| hex).unwrap();
+        .flatten()
+        .map(|&x| hex_to_rgb(x))
+        .collect::<Vec<(u8, u8, u8)>>();
+
+    if pixels.is_empty() || count == 0 {
+        return Vec::new();
+    }
+
+    let channel = |rgb: &(u8, u8, u8), c: usize| match c {
+        0 => rgb.0,
+        1 => rgb.1,
+        _ => rgb.2,
+    };
+
+    let range = |bucket: &[(u8, u8, u8)], c: usize| {
+        let min = bucket.iter().map(|x| channel(x, c)).min().unwrap_or(0);
+        let max = bucket.iter().map(|x| channel(x, c)).max().unwrap_or(0);
+        max - min
+    };
+
+    // median cut, always splitting the bucket with the widest channel
+    let mut buckets = vec![pixels.clone()];
+    while buckets.len() < count {
+        let widest = buckets
+            .iter()
+            .enumerate()
+            .map(|(i, bucket)| (i, (0..3).map(|c| (range(bucket, c), c)).max().unwrap()))
+            .max_by_key(|(_, (range, _))| *range);
+
+        match widest {
+            Some((i, (range, c))) if range > 0 => {
+                let mut bucket = buckets.swap_remove(i);
+                bucket.sort_by_key(|x| channel(x, c));
+                let upper = bucket.split_off(bucket.len() / 2);
+                buckets.push(bucket);
+                buckets.push(upper);
+            }
+            _ => break,
+        }
+    }
+
+    let mean = |bucket: &[(u8, u8, u8)]| {
+        let len = bucket.len().max(1) as u32;
+        let sum = bucket.iter().fold((0, 0, 0), |acc, x| {
+            (acc.0 + x.0 as u32, acc.1 + x.1 as u32, acc.2 + x.2 as u32)
+        });
+        ((sum.0 / len) as u8, (sum.1 / len) as u8, (sum.2 / len) as u8)
+    };
+
+    let mut centroids = buckets.iter().map(|x| mean(x)).collect::<Vec<(u8, u8, u8)>>();
+    let mut clusters: Vec<Vec<(u8, u8, u8)>> = Vec::new();
+
+    for _ in 0..8 {
+        clusters = vec![Vec::new(); centroids.len()];
+
+        for pixel in pixels.iter() {
+            let nearest = centroids
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, &centroid)| color_distance_squared(*pixel, centroid))
+                .map(|(i, _)| i)
+                .unwrap();
+
+            clusters[nearest].push(*pixel);
+        }
 
-    let index = hex_colors.iter().position(|&x| x == hex).unwrap();
-    return index as u8;
+        for (centroid, cluster) in centroids.iter_mut().zip(clusters.iter()) {
+            if !cluster.is_empty() {
+                *centroid = mean(cluster);
+            }
+        }
+    }
 
-}
\ No newline at end of file
+    let mut ranked = centroids.into_iter().zip(clusters.iter().map(|x| x.len())).collect::<Vec<_>>();
+    ranked.sort_by_key(|(_, len)| std::cmp::Reverse(*len));
+
+    ranked
+        .into_iter()
+        .map(|((r, g, b), _)| ((r as u32) << 16) + ((g as u32) << 8) + b as u32)
+        .collect()
+}
+
+// gimp palette, "r g b name" per line after the header
+pub fn parse_gpl(contents: &str) -> Result<Vec<u32>, String> {
+    let mut lines = contents.lines();
+
+    if lines.next().map(|x| x.trim()) != Some("GIMP Palette") {
+        return Err("not a gimp palette".to_string());
+    }
+
+    let mut colors = Vec::new();
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.contains(':') {
+            continue;
+        }
+
+        let rgb = line
+            .split_whitespace()
+            .take(3)
+            .map(|x| x.parse::<u8>().map_err(|_| format!("invalid gimp palette line '{}'", line)))
+            .collect::<Result<Vec<u8>, String>>()?;
+
+        match rgb.as_slice() {
+            [r, g, b] => colors.push(((*r as u32) << 16) + ((*g as u32) << 8) + *b as u32),
+            _ => return Err(format!("invalid gimp palette line '{}'", line)),
+        }
+    }
+
+    if colors.is_empty() {
+        return Err("gimp palette has no colours".to_string());
+    }
+
+    Ok(colors)
+}
+
+pub fn format_gpl(name: &str, colors: &[u32]) -> String {
+    let mut out = format!("GIMP Palette\nName: {}\nColumns: 8\n#\n", name);
+
+    for &color in colors {
+        let (r, g, b) = hex_to_rgb(color);
+        out.push_str(&format!("{:>3} {:>3} {:>3}\t#{:06x}\n", r, g, b, color));
+    }
+
+    out
+}
+
+pub fn map_hex_colors(bitmap: &[Vec<u32>], palette: &Palette) -> Vec<Vec<u8>> {
+    bitmap
+        .iter()
+        .map(|row| row.iter().map(|x| palette.nearest(*x)).collect())
+        .collect()
+}
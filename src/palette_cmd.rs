@@ -0,0 +1,114 @@
+use crate::args::{Args, PaletteAction};
+use crate::palette::{self, ColorMetric, Palette};
+use crate::{draw, theme};
+use std::{error::Error, fs, path::Path};
+
+const SWATCHES_PER_ROW: usize = 8;
+
+pub async fn run(action: &PaletteAction, args: &Args) -> Result<(), Box<dyn Error>> {
+    match action {
+        PaletteAction::Show { palette } => show(palette, args),
+        PaletteAction::Nearest { color } => nearest(*color, args),
+        PaletteAction::Extract { image, n, output } => extract(image, *n, output.as_deref()).await,
+    }
+}
+
+fn named_palette(name: &str, args: &Args) -> Result<Palette, Box<dyn Error>> {
+    match name {
+        "irc" => Ok(palette::irc_palette(args)),
+        "irc16" => Ok(palette::irc16_palette()),
+        "ansi" => Ok(palette::ansi_palette(args)),
+        "ansi16" => Ok(palette::ansi16_palette(args)),
+        _ if name.to_lowercase().ends_with(".gpl") => {
+            let contents = fs::read_to_string(name).map_err(|e| format!("{}: {}", name, e))?;
+            Ok(Palette::new(&palette::parse_gpl(&contents)?))
+        }
+        _ => Ok(Palette::new(&theme::load_theme(name)?.0)),
+    }
+}
+
+fn show(name: &str, args: &Args) -> Result<(), Box<dyn Error>> {
+    let shown = named_palette(name, args)?;
+    let target = palette::target_palette(args);
+
+    for (row, colors) in shown.colors.chunks(SWATCHES_PER_ROW).enumerate() {
+        let line = colors
+            .iter()
+            .enumerate()
+            .map(|(i, &color)| {
+                let label = format!(" {:>3} #{:06x} ", row * SWATCHES_PER_ROW + i, color);
+                swatch(color, &label, &target, args)
+            })
+            .collect::<String>();
+
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+// the label is drawn in black or white, whichever reads better on the colour
+fn swatch(color: u32, label: &str, target: &Palette, args: &Args) -> String {
+    let (r, g, b) = palette::hex_to_rgb(color);
+    let text = match palette::luma(color) > 127.0 {
+        true => 0x000000,
+        false => 0xffffff,
+    };
+
+    if args.truecolor() {
+        let [tr, tg, tb] = draw::make_rgb_u8(text);
+        return format!("\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m{}\x1b[0m", tr, tg, tb, r, g, b, label);
+    }
+
+    let (fg, bg) = (target.nearest(text), target.nearest(color));
+
+    match (args.irc || args.irc16, args.ansi, args.ansi16) {
+        (true, _, _) => format!("\x03{:02},{:02}{}\x0f", fg, bg, label),
+        (_, true, _) => format!("\x1b[38;5;{}m\x1b[48;5;{}m{}\x1b[0m", fg, bg, label),
+        (_, _, true) => format!("{}{}\x1b[0m", draw::ansi16_sgr(Some(fg), Some(bg)), label),
+        _ => format!("\x03{:02},{:02}{}\x0f", fg, bg, label),
+    }
+}
+
+fn nearest(color: u32, args: &Args) -> Result<(), Box<dyn Error>> {
+    let palettes = [
+        ("irc", palette::irc_palette(args)),
+        ("irc16", palette::irc16_palette()),
+        ("ansi", palette::ansi_palette(args)),
+        ("ansi16", palette::ansi16_palette(args)),
+    ];
+
+    for (name, palette) in palettes.iter() {
+        for metric in [ColorMetric::Rgb, ColorMetric::Redmean, ColorMetric::Cielab] {
+            let index = palette.nearest_by(color, metric);
+            println!(
+                "{:<7} {:<8} {:>3} #{:06x}",
+                name,
+                metric.name(),
+                index,
+                palette.colors[index as usize],
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn extract(image: &str, n: u8, output: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let image = crate::load_image_from_url_or_path(image).await?;
+    let colors = Palette::from_image(&image, n as usize).colors;
+
+    let name = output
+        .and_then(|x| Path::new(x).file_stem())
+        .and_then(|x| x.to_str())
+        .unwrap_or("img2irc");
+
+    let gpl = palette::format_gpl(name, &colors);
+
+    match output {
+        Some(path) => fs::write(path, gpl).map_err(|e| format!("{}: {}", path, e))?,
+        None => print!("{}", gpl),
+    }
+
+    Ok(())
+}
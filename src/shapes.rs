@@ -0,0 +1,380 @@
+use crate::draw::{cell_positions, make_rgb_u8, make_rgb_u32, mean_color, AnsiCell, AnsiImage, AnsiPixel, FIT_CANDIDATES, QUADRANTS, SEXTANTS, SHADES};
+use clap::ValueEnum;
+
+// sub-pixels sampled per character cell, (columns, rows)
+pub const SHAPE_RESOLUTION: (u32, u32) = (4, 8);
+
+// ▁ to ▇ lower eighths, █, then ▉ to ▏ left eighths
+const EIGHTHS: [&str; 15] = [
+    "\u{2581}", "\u{2582}", "\u{2583}", "\u{2584}", "\u{2585}", "\u{2586}", "\u{2587}", "\u{2588}",
+    "\u{2589}", "\u{258A}", "\u{258B}", "\u{258C}", "\u{258D}", "\u{258E}", "\u{258F}",
+];
+
+// corner points in cell units, (x, y) from the top left
+type Polygon = &'static [(f32, f32)];
+
+// thirds of the cell height the wedges are drawn between
+const T: f32 = 1.0 / 3.0;
+const M: f32 = 2.0 / 3.0;
+
+// 🬼 to 🭑 and 🭬 to 🭯 as fg polygons in cell units, each with the wedge that fills the rest of the cell
+const WEDGES: [(&str, &str, Polygon); 26] = [
+    ("\u{1FB3C}", "\u{1FB52}", &[(0.0, M), (0.0, 1.0), (0.5, 1.0)]),
+    ("\u{1FB3D}", "\u{1FB53}", &[(0.0, M), (0.0, 1.0), (1.0, 1.0)]),
+    ("\u{1FB3E}", "\u{1FB54}", &[(0.0, T), (0.0, 1.0), (0.5, 1.0)]),
+    ("\u{1FB3F}", "\u{1FB55}", &[(0.0, T), (0.0, 1.0), (1.0, 1.0)]),
+    ("\u{1FB40}", "\u{1FB56}", &[(0.0, 0.0), (0.0, 1.0), (0.5, 1.0)]),
+    ("\u{1FB41}", "\u{1FB57}", &[(0.0, T), (0.5, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]),
+    ("\u{1FB42}", "\u{1FB58}", &[(0.0, T), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]),
+    ("\u{1FB43}", "\u{1FB59}", &[(0.0, M), (0.5, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]),
+    ("\u{1FB44}", "\u{1FB5A}", &[(0.0, M), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]),
+    ("\u{1FB45}", "\u{1FB5B}", &[(0.0, 1.0), (0.5, 0.0), (1.0, 0.0), (1.0, 1.0)]),
+    ("\u{1FB46}", "\u{1FB5C}", &[(0.0, M), (1.0, T), (1.0, 1.0), (0.0, 1.0)]),
+    ("\u{1FB47}", "\u{1FB5D}", &[(0.5, 1.0), (1.0, M), (1.0, 1.0)]),
+    ("\u{1FB48}", "\u{1FB5E}", &[(0.0, 1.0), (1.0, M), (1.0, 1.0)]),
+    ("\u{1FB49}", "\u{1FB5F}", &[(0.5, 1.0), (1.0, T), (1.0, 1.0)]),
+    ("\u{1FB4A}", "\u{1FB60}", &[(0.0, 1.0), (1.0, T), (1.0, 1.0)]),
+    ("\u{1FB4B}", "\u{1FB61}", &[(0.5, 1.0), (1.0, 0.0), (1.0, 1.0)]),
+    ("\u{1FB4C}", "\u{1FB62}", &[(0.0, 0.0), (0.5, 0.0), (1.0, T), (1.0, 1.0), (0.0, 1.0)]),
+    ("\u{1FB4D}", "\u{1FB63}", &[(0.0, 0.0), (1.0, T), (1.0, 1.0), (0.0, 1.0)]),
+    ("\u{1FB4E}", "\u{1FB64}", &[(0.0, 0.0), (0.5, 0.0), (1.0, M), (1.0, 1.0), (0.0, 1.0)]),
+    ("\u{1FB4F}", "\u{1FB65}", &[(0.0, 0.0), (1.0, M), (1.0, 1.0), (0.0, 1.0)]),
+    ("\u{1FB50}", "\u{1FB66}", &[(0.0, 0.0), (0.5, 0.0), (1.0, 1.0), (0.0, 1.0)]),
+    ("\u{1FB51}", "\u{1FB67}", &[(0.0, T), (1.0, M), (1.0, 1.0), (0.0, 1.0)]),
+    ("\u{1FB6C}", "\u{1FB68}", &[(0.0, 0.0), (0.5, 0.5), (0.0, 1.0)]),
+    ("\u{1FB6D}", "\u{1FB69}", &[(0.0, 0.0), (1.0, 0.0), (0.5, 0.5)]),
+    ("\u{1FB6E}", "\u{1FB6A}", &[(1.0, 0.0), (1.0, 1.0), (0.5, 0.5)]),
+    ("\u{1FB6F}", "\u{1FB6B}", &[(0.0, 1.0), (1.0, 1.0), (0.5, 0.5)]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GlyphSet {
+    Eighths,
+    Shades,
+    Quadrants,
+    Sextants,
+    Wedges,
+}
+
+// a glyph as fg rectangles (x0, y0, x1, y1) and polygons in cell units over bg, density is how much fg shows inside them,
+// inverted glyphs draw fg everywhere but those
+struct Shape {
+    char: &'static str,
+    rects: Vec<(f32, f32, f32, f32)>,
+    polygons: Vec<Vec<(f32, f32)>>,
+    inverted: bool,
+    density: f32,
+}
+
+impl Shape {
+    fn new(char: &'static str, rects: Vec<(f32, f32, f32, f32)>) -> Shape {
+        Shape { char, rects, polygons: Vec::new(), inverted: false, density: 1.0 }
+    }
+
+    fn wedge(char: &'static str, polygon: &[(f32, f32)], inverted: bool) -> Shape {
+        Shape { char, rects: Vec::new(), polygons: vec![polygon.to_vec()], inverted, density: 1.0 }
+    }
+
+    // how much of each sub-pixel is fg, in the same order as cell_positions
+    fn coverage(&self) -> Vec<f32> {
+        let (columns, rows) = (SHAPE_RESOLUTION.0 as usize, SHAPE_RESOLUTION.1 as usize);
+        let (width, height) = (1.0 / columns as f32, 1.0 / rows as f32);
+
+        (0..rows)
+            .flat_map(|y| (0..columns).map(move |x| (x as f32 * width, y as f32 * height)))
+            .map(|(px, py)| {
+                let area = self
+                    .rects
+                    .iter()
+                    .map(|&(x0, y0, x1, y1)| {
+                        let w = (x1.min(px + width) - x0.max(px)).max(0.0);
+                        let h = (y1.min(py + height) - y0.max(py)).max(0.0);
+                        w * h
+                    })
+                    .sum::<f32>()
+                    + self
+                        .polygons
+                        .iter()
+                        .map(|polygon| clipped_area(polygon, (px, py, px + width, py + height)))
+                        .sum::<f32>();
+
+                let covered = (area / (width * height)).min(1.0);
+                match self.inverted {
+                    true => (1.0 - covered) * self.density,
+                    false => covered * self.density,
+                }
+            })
+            .collect()
+    }
+}
+
+// area of a polygon inside the rectangle (x0, y0, x1, y1), sutherland-hodgman clipped to each side then the shoelace sum
+fn clipped_area(polygon: &[(f32, f32)], (x0, y0, x1, y1): (f32, f32, f32, f32)) -> f32 {
+    // how far inside each side a point is
+    let sides: [&dyn Fn((f32, f32)) -> f32; 4] = [&|p| p.0 - x0, &|p| x1 - p.0, &|p| p.1 - y0, &|p| y1 - p.1];
+
+    let mut points = polygon.to_vec();
+    for inside in sides {
+        let mut clipped = Vec::new();
+
+        for (i, &a) in points.iter().enumerate() {
+            let b = points[(i + 1) % points.len()];
+            let (da, db) = (inside(a), inside(b));
+
+            if da >= 0.0 {
+                clipped.push(a);
+            }
+            if (da >= 0.0) != (db >= 0.0) {
+                let t = da / (da - db);
+                clipped.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+            }
+        }
+
+        points = clipped;
+        if points.is_empty() {
+            return 0.0;
+        }
+    }
+
+    let twice = (0..points.len())
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum::<f32>();
+
+    twice.abs() / 2.0
+}
+
+// space and full block are always available, sets overlapping on ▌ ▐ ▀ ▄ are only kept once
+fn shapes(sets: &[GlyphSet]) -> Vec<Shape> {
+    let mut shapes = vec![Shape::new(" ", vec![]), Shape::new("\u{2588}", vec![(0.0, 0.0, 1.0, 1.0)])];
+
+    for set in sets {
+        match set {
+            GlyphSet::Eighths => {
+                for k in 1..8 {
+                    let size = k as f32 / 8.0;
+                    shapes.push(Shape::new(EIGHTHS[k - 1], vec![(0.0, 1.0 - size, 1.0, 1.0)]));
+                    shapes.push(Shape::new(EIGHTHS[15 - k], vec![(0.0, 0.0, size, 1.0)]));
+                }
+            }
+            GlyphSet::Shades => {
+                for (char, density) in SHADES {
+                    shapes.push(Shape { density, ..Shape::new(char, vec![(0.0, 0.0, 1.0, 1.0)]) });
+                }
+            }
+            GlyphSet::Quadrants => {
+                for (mask, &char) in QUADRANTS.iter().enumerate() {
+                    let rects = (0..4)
+                        .filter(|bit| mask >> bit & 1 == 1)
+                        .map(|bit| {
+                            let (x, y) = ((bit % 2) as f32 / 2.0, (bit / 2) as f32 / 2.0);
+                            (x, y, x + 0.5, y + 0.5)
+                        })
+                        .collect();
+
+                    shapes.push(Shape::new(char, rects));
+                }
+            }
+            GlyphSet::Wedges => {
+                for (char, inverse, polygon) in WEDGES {
+                    shapes.push(Shape::wedge(char, polygon, false));
+                    shapes.push(Shape::wedge(inverse, polygon, true));
+                }
+            }
+            GlyphSet::Sextants => {
+                for (mask, &char) in SEXTANTS.iter().enumerate() {
+                    let rects = (0..6)
+                        .filter(|bit| mask >> bit & 1 == 1)
+                        .map(|bit| {
+                            let (x, y) = ((bit % 2) as f32 / 2.0, (bit / 2) as f32 / 3.0);
+                            (x, y, x + 0.5, y + 1.0 / 3.0)
+                        })
+                        .collect();
+
+                    shapes.push(Shape::new(char, rects));
+                }
+            }
+        }
+    }
+
+    let mut seen = Vec::new();
+    shapes.retain(|shape| match seen.contains(&shape.char) {
+        true => false,
+        false => {
+            seen.push(shape.char);
+            true
+        }
+    });
+
+    shapes
+}
+
+// sums over a cell that let the error of any fg/bg pair be worked out without going back over the pixels
+struct Sums {
+    fg_fg: f32,
+    bg_bg: f32,
+    fg_bg: f32,
+    fg_color: [f32; 3],
+    bg_color: [f32; 3],
+}
+
+impl Sums {
+    fn new(coverage: &[f32], colors: &[[f32; 3]]) -> Sums {
+        let mut sums = Sums { fg_fg: 0.0, bg_bg: 0.0, fg_bg: 0.0, fg_color: [0.0; 3], bg_color: [0.0; 3] };
+
+        for (&a, color) in coverage.iter().zip(colors) {
+            sums.fg_fg += a * a;
+            sums.bg_bg += (1.0 - a) * (1.0 - a);
+            sums.fg_bg += a * (1.0 - a);
+
+            for (c, &x) in color.iter().enumerate() {
+                sums.fg_color[c] += a * x;
+                sums.bg_color[c] += (1.0 - a) * x;
+            }
+        }
+
+        sums
+    }
+
+    // squared error of drawing fg over bg, less the constant sum of the squared pixel colours
+    fn error(&self, fg: [f32; 3], bg: [f32; 3]) -> f32 {
+        (0..3)
+            .map(|c| {
+                self.fg_fg * fg[c] * fg[c] + self.bg_bg * bg[c] * bg[c] + 2.0 * self.fg_bg * fg[c] * bg[c]
+                    - 2.0 * self.fg_color[c] * fg[c]
+                    - 2.0 * self.bg_color[c] * bg[c]
+            })
+            .sum()
+    }
+
+    // least squares fg and bg, glyphs without any edge get the cell mean for both
+    fn solve(&self, mean: [f32; 3]) -> ([f32; 3], [f32; 3]) {
+        let det = self.fg_fg * self.bg_bg - self.fg_bg * self.fg_bg;
+        if det.abs() < 1e-3 {
+            return (mean, mean);
+        }
+
+        let (mut fg, mut bg) = ([0.0; 3], [0.0; 3]);
+        for c in 0..3 {
+            fg[c] = ((self.bg_bg * self.fg_color[c] - self.fg_bg * self.bg_color[c]) / det).clamp(0.0, 255.0);
+            bg[c] = ((self.fg_fg * self.bg_color[c] - self.fg_bg * self.fg_color[c]) / det).clamp(0.0, 255.0);
+        }
+
+        (fg, bg)
+    }
+}
+
+// picks the glyph whose coverage with its best fg/bg pair is closest to the sub-pixels of each cell
+pub fn shape_cells(image: &AnsiImage, sets: &[GlyphSet]) -> Vec<Vec<AnsiCell>> {
+    let shapes = shapes(sets);
+    let coverages = shapes.iter().map(|x| x.coverage()).collect::<Vec<Vec<f32>>>();
+    let (columns, rows) = (SHAPE_RESOLUTION.0 as usize, SHAPE_RESOLUTION.1 as usize);
+
+    let palette = &image.palette;
+
+    let to_f32 = |color: u32| make_rgb_u8(color).map(|x| x as f32);
+    let to_u32 = |color: [f32; 3]| make_rgb_u32(color.iter().map(|x| x.round() as u8).collect());
+
+    cell_positions(image, columns, rows)
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|positions| {
+                    let hex_colors = positions.iter().map(|&(y, x)| image.target(y, x)).collect::<Vec<u32>>();
+                    let colors = hex_colors.iter().map(|&x| to_f32(x)).collect::<Vec<[f32; 3]>>();
+                    let mean = mean_color(&hex_colors).unwrap();
+                    let sums = coverages.iter().map(|x| Sums::new(x, &colors)).collect::<Vec<Sums>>();
+
+                    // the best glyph with exact colours
+                    let (mut best, mut best_error) = ((0, to_f32(mean), to_f32(mean)), f32::MAX);
+                    for (i, sums) in sums.iter().enumerate() {
+                        let (fg, bg) = sums.solve(to_f32(mean));
+                        let error = sums.error(fg, bg);
+
+                        if error < best_error {
+                            best_error = error;
+                            best = (i, fg, bg);
+                        }
+                    }
+
+                    if image.truecolor {
+                        let pixel = |color: [f32; 3]| {
+                            let orig = to_u32(color);
+                            AnsiPixel { orig, index: palette.nearest(orig) }
+                        };
+
+                        return AnsiCell {
+                            char: shapes[best.0].char,
+                            fg: pixel(best.1),
+                            bg: Some(pixel(best.2)),
+                        };
+                    }
+
+                    // palette pairs from the matched indices, the entries nearest the mean and the exact fit
+                    let mut candidates = positions.iter().map(|&(y, x)| image.indices[y][x]).collect::<Vec<u8>>();
+                    candidates.extend(palette.nearest_n(mean, FIT_CANDIDATES));
+                    candidates.extend([palette.nearest(to_u32(best.1)), palette.nearest(to_u32(best.2))]);
+                    candidates.sort();
+                    candidates.dedup();
+
+                    let entries = candidates.iter().map(|&x| to_f32(palette.colors[x as usize])).collect::<Vec<[f32; 3]>>();
+
+                    let (mut best, mut best_error) = ((0, candidates[0], candidates[0]), f32::MAX);
+                    for (i, sums) in sums.iter().enumerate() {
+                        for (f, &fg) in candidates.iter().enumerate() {
+                            for (b, &bg) in candidates.iter().enumerate() {
+                                let error = sums.error(entries[f], entries[b]);
+
+                                if error < best_error {
+                                    best_error = error;
+                                    best = (i, fg, bg);
+                                }
+                            }
+                        }
+                    }
+
+                    let pixel = |index: u8| AnsiPixel {
+                        orig: palette.colors[index as usize],
+                        index,
+                    };
+
+                    AnsiCell {
+                        char: shapes[best.0].char,
+                        fg: pixel(best.1),
+                        bg: Some(pixel(best.2)),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clipped_area_of_a_triangle() {
+        let triangle = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)];
+        assert!((clipped_area(&triangle, (0.0, 0.0, 1.0, 1.0)) - 0.5).abs() < 1e-6);
+        assert!((clipped_area(&triangle, (0.0, 0.0, 0.5, 0.5)) - 0.25).abs() < 1e-6);
+        assert!((clipped_area(&triangle, (0.5, 0.5, 1.0, 1.0))).abs() < 1e-6);
+    }
+
+    #[test]
+    fn wedges_and_their_inverse_fill_the_cell() {
+        let wedges = shapes(&[GlyphSet::Wedges]);
+        assert_eq!(wedges.len(), 2 + 52);
+
+        for pair in wedges[2..].chunks(2) {
+            let (wedge, inverse) = (pair[0].coverage(), pair[1].coverage());
+            assert!(wedge.iter().zip(&inverse).all(|(a, b)| (a + b - 1.0).abs() < 1e-5), "{}", pair[0].char);
+        }
+
+        // 🭬 covers the left quarter of the cell
+        let left = wedges.iter().find(|x| x.char == "\u{1FB6C}").unwrap().coverage();
+        assert!((left.iter().sum::<f32>() / left.len() as f32 - 0.25).abs() < 1e-5);
+    }
+}
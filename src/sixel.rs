@@ -0,0 +1,79 @@
+use crate::draw::AnsiImage;
+use crate::palette::{hex_to_rgb, Palette};
+use photon_rs::PhotonImage;
+
+// colour registers most sixel terminals give us
+const REGISTERS: usize = 256;
+
+// runs at least this long are written as !<count><sixel>
+const MIN_RUN: usize = 4;
+
+// adaptive registers unless a palette mode is chosen, those use their own palette as is
+pub fn registers(image: &PhotonImage) -> Palette {
+    Palette::from_image(image, REGISTERS)
+}
+
+// every palette index in use becomes a register, then each band of six rows is drawn once per colour
+pub fn sixel_draw(image: &AnsiImage) -> String {
+    let (width, height) = (image.image.get_width() as usize, image.image.get_height() as usize);
+    let indices = &image.indices[..height];
+
+    let mut out = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+
+    let mut used = [false; REGISTERS];
+    for &index in indices.iter().flatten() {
+        used[index as usize] = true;
+    }
+
+    for (index, _) in used.iter().enumerate().filter(|(_, &used)| used) {
+        let (r, g, b) = hex_to_rgb(image.palette.colors[index]);
+        let percent = |x: u8| (x as u32 * 100 + 127) / 255;
+        out.push_str(&format!("#{};2;{};{};{}", index, percent(r), percent(g), percent(b)));
+    }
+
+    for (n, band) in indices.chunks(6).enumerate() {
+        // the six bits of each column, per colour in the band
+        let mut bands: Vec<Option<Vec<u8>>> = vec![None; REGISTERS];
+        for (y, row) in band.iter().enumerate() {
+            for (x, &index) in row.iter().enumerate() {
+                bands[index as usize].get_or_insert_with(|| vec![0; width])[x] |= 1 << y;
+            }
+        }
+
+        let colors = bands.iter().enumerate().filter_map(|(i, bits)| bits.as_ref().map(|x| (i, x))).collect::<Vec<_>>();
+
+        for (i, (color, bits)) in colors.iter().enumerate() {
+            let sixels = bits.iter().map(|&x| (63 + x) as char).collect::<Vec<char>>();
+
+            out.push_str(&format!("#{}", color));
+            push_runs(&mut out, &sixels);
+
+            // back to the start of the band for the next colour
+            if i != colors.len() - 1 {
+                out.push('$');
+            }
+        }
+
+        // down to the next band
+        if n != height.div_ceil(6) - 1 {
+            out.push('-');
+        }
+    }
+
+    out.push_str("\x1b\\");
+    out
+}
+
+fn push_runs(out: &mut String, sixels: &[char]) {
+    let mut start = 0;
+    while start < sixels.len() {
+        let run = sixels[start..].iter().take_while(|&&x| x == sixels[start]).count();
+
+        match run {
+            _ if run >= MIN_RUN => out.push_str(&format!("!{}{}", run, sixels[start])),
+            _ => (0..run).for_each(|_| out.push(sixels[start])),
+        }
+
+        start += run;
+    }
+}
@@ -0,0 +1,272 @@
+use crate::palette::{parse_hex_color, Ansi16Colors};
+use std::fs;
+
+const NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+// loads the 16 basic colours from a terminal colour scheme, the format is picked by extension
+pub fn load_theme(path: &str) -> Result<Ansi16Colors, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse_theme(path, &contents).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse_theme(path: &str, contents: &str) -> Result<Ansi16Colors, String> {
+    let lower = path.to_lowercase();
+
+    let colors = match lower.rsplit('.').next() {
+        Some("itermcolors") => parse_itermcolors(contents),
+        Some("json") => parse_windows_terminal(contents),
+        Some("toml") | Some("yml") | Some("yaml") => parse_alacritty(contents),
+        _ => parse_xresources(contents),
+    };
+
+    let mut hex_colors = [0; 16];
+    for (i, color) in colors.iter().enumerate() {
+        match color {
+            Some(hex) => hex_colors[i] = *hex,
+            None => return Err(format!("theme is missing colour {}", i)),
+        }
+    }
+
+    Ok(Ansi16Colors(hex_colors))
+}
+
+fn parse_theme_color(value: &str) -> Option<u32> {
+    let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+
+    // xresources also allows rgb:r/g/b with 1 to 4 hex digits per channel, scaled to 8 bits
+    if let Some(rgb) = value.strip_prefix("rgb:") {
+        let channels = rgb
+            .split('/')
+            .map(|x| match x.len() {
+                1..=4 if x.chars().all(|c| c.is_ascii_hexdigit()) => {
+                    let max = (1 << (4 * x.len())) - 1;
+                    u32::from_str_radix(x, 16).ok().map(|v| (v * 255 + max / 2) / max)
+                }
+                _ => None,
+            })
+            .collect::<Option<Vec<u32>>>()?;
+
+        return match channels.as_slice() {
+            [r, g, b] => Some((r << 16) + (g << 8) + b),
+            _ => None,
+        };
+    }
+
+    parse_hex_color(value).ok()
+}
+
+// *.color0: #000000, URxvt.color0: #000000 etc
+fn parse_xresources(contents: &str) -> [Option<u32>; 16] {
+    let mut colors = [None; 16];
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('!') || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once(':') {
+            let index = key
+                .trim()
+                .rsplit("color")
+                .next()
+                .and_then(|x| x.parse::<usize>().ok());
+
+            if let Some(index @ 0..=15) = index {
+                colors[index] = parse_theme_color(value);
+            }
+        }
+    }
+
+    colors
+}
+
+// [colors.normal] / [colors.bright] tables in toml, or normal: / bright: maps in yaml
+fn parse_alacritty(contents: &str) -> [Option<u32>; 16] {
+    let mut colors = [None; 16];
+    let mut offset: Option<usize> = None;
+
+    for line in contents.lines() {
+        let line = line.split(" #").next().unwrap_or("").trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line {
+            "[colors.normal]" | "normal:" => {
+                offset = Some(0);
+                continue;
+            }
+            "[colors.bright]" | "bright:" => {
+                offset = Some(8);
+                continue;
+            }
+            _ if line.starts_with('[') || line.ends_with(':') => {
+                offset = None;
+                continue;
+            }
+            _ => {}
+        }
+
+        let pair = line.split_once('=').or_else(|| line.split_once(':'));
+
+        if let (Some(offset), Some((key, value))) = (offset, pair) {
+            if let Some(index) = NAMES.iter().position(|&name| name == key.trim()) {
+                colors[offset + index] = parse_theme_color(value);
+            }
+        }
+    }
+
+    colors
+}
+
+// "black": "#0C0C0C", "brightBlack": "#767676" etc, windows terminal calls magenta purple
+fn parse_windows_terminal(contents: &str) -> [Option<u32>; 16] {
+    let mut colors = [None; 16];
+    let strings = contents.split('"').collect::<Vec<&str>>();
+
+    for i in (1..strings.len().saturating_sub(2)).step_by(2) {
+        if strings[i + 1].trim() != ":" {
+            continue;
+        }
+
+        let key = strings[i].replace("purple", "magenta").replace("Purple", "Magenta");
+        let (offset, name) = match key.strip_prefix("bright") {
+            Some(name) => (8, name.to_lowercase()),
+            None => (0, key),
+        };
+
+        if let Some(index) = NAMES.iter().position(|&x| x == name) {
+            if colors[offset + index].is_none() {
+                colors[offset + index] = parse_theme_color(strings[i + 2]);
+            }
+        }
+    }
+
+    colors
+}
+
+// <key>Ansi 0 Color</key><dict>...<key>Red Component</key><real>0.5</real>...</dict>
+fn parse_itermcolors(contents: &str) -> [Option<u32>; 16] {
+    let mut colors = [None; 16];
+
+    for (i, color) in colors.iter_mut().enumerate() {
+        let key = format!("<key>Ansi {} Color</key>", i);
+
+        let dict = contents
+            .split_once(key.as_str())
+            .and_then(|(_, rest)| rest.split_once("</dict>"))
+            .map(|(dict, _)| dict);
+
+        if let Some(dict) = dict {
+            let component = |name: &str| -> Option<u32> {
+                let (_, rest) = dict.split_once(format!("<key>{} Component</key>", name).as_str())?;
+                let (_, rest) = rest.split_once("<real>")?;
+                let (value, _) = rest.split_once("</real>")?;
+                let value = value.trim().parse::<f32>().ok()?;
+
+                Some((value.clamp(0.0, 1.0) * 255.0).round() as u32)
+            };
+
+            if let (Some(r), Some(g), Some(b)) = (component("Red"), component("Green"), component("Blue")) {
+                *color = Some((r << 16) + (g << 8) + b);
+            }
+        }
+    }
+
+    colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0x000000, 0x111111 ... 0xffffff
+    fn expected() -> [u32; 16] {
+        let mut colors = [0; 16];
+        for (i, x) in colors.iter_mut().enumerate() {
+            *x = i as u32 * 0x111111;
+        }
+        colors
+    }
+
+    fn hex(i: usize) -> String {
+        format!("#{:06x}", expected()[i])
+    }
+
+    #[test]
+    fn xresources() {
+        let mut contents = String::from("! comment\n*.foreground: #ffffff\n");
+        for i in 0..15 {
+            contents.push_str(&format!("*.color{}: {}\n", i, hex(i)));
+        }
+        contents.push_str("URxvt.color15: rgb:ff/ff/ff\n");
+
+        assert_eq!(parse_theme("theme.Xresources", &contents).unwrap().0, expected());
+    }
+
+    #[test]
+    fn alacritty() {
+        let mut contents = String::from("[colors.primary]\nbackground = '#123456'\n");
+        for (table, offset) in [("normal", 0), ("bright", 8)] {
+            contents.push_str(&format!("[colors.{}]\n", table));
+            for (i, name) in NAMES.iter().enumerate() {
+                contents.push_str(&format!("{} = '{}' # {}\n", name, hex(offset + i), name));
+            }
+        }
+
+        assert_eq!(parse_theme("theme.toml", &contents).unwrap().0, expected());
+    }
+
+    #[test]
+    fn windows_terminal() {
+        let mut fields = vec!["\"name\": \"test\"".to_string(), "\"background\": \"#123456\"".to_string()];
+        for (i, name) in NAMES.iter().enumerate() {
+            let name = name.replace("magenta", "purple");
+            let mut bright = name.clone();
+            bright[..1].make_ascii_uppercase();
+
+            fields.push(format!("\"{}\": \"{}\"", name, hex(i)));
+            fields.push(format!("\"bright{}\": \"{}\"", bright, hex(8 + i)));
+        }
+        let contents = format!("{{\n    {}\n}}", fields.join(",\n    "));
+
+        assert_eq!(parse_theme("theme.json", &contents).unwrap().0, expected());
+    }
+
+    #[test]
+    fn itermcolors() {
+        let mut contents = String::from("<plist version=\"1.0\">\n<dict>\n");
+        for i in 0..16 {
+            let value = (expected()[i] & 0xff) as f32 / 255.0;
+            contents.push_str(&format!("\t<key>Ansi {} Color</key>\n\t<dict>\n", i));
+            for component in ["Blue", "Green", "Red"] {
+                contents.push_str(&format!("\t\t<key>{} Component</key>\n\t\t<real>{}</real>\n", component, value));
+            }
+            contents.push_str("\t</dict>\n");
+        }
+        contents.push_str("</dict>\n</plist>\n");
+
+        assert_eq!(parse_theme("theme.itermcolors", &contents).unwrap().0, expected());
+    }
+
+    #[test]
+    fn xresources_rgb_digits() {
+        assert_eq!(parse_theme_color("rgb:f/f/f"), Some(0xffffff));
+        assert_eq!(parse_theme_color("rgb:8/0/f"), Some(0x8800ff));
+        assert_eq!(parse_theme_color("rgb:ff/80/00"), Some(0xff8000));
+        assert_eq!(parse_theme_color("rgb:fff/000/800"), Some(0xff0080));
+        assert_eq!(parse_theme_color("rgb:ffff/0000/8000"), Some(0xff0080));
+        assert_eq!(parse_theme_color("rgb:fffff/0/0"), None);
+        assert_eq!(parse_theme_color("rgb://0"), None);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(parse_theme("theme.Xresources", "*.color0: #000000\n*.color1 #zzzzzz\n").is_err());
+        assert!(parse_theme("theme.Xresources", "*.color0: rgb:\u{20ac}/00/00\n").is_err());
+        assert!(parse_theme("theme.toml", "[colors.normal]\nblack = \n").is_err());
+        assert!(parse_theme("theme.json", "{ \"black\": ").is_err());
+        assert!(parse_theme("theme.itermcolors", "<key>Ansi 0 Color</key><dict><real>x</real>").is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use crate::palette::luma;
+use photon_rs::PhotonImage;
+
+// how far a colour balance of 100 moves a channel at full weight
+const BALANCE_SHIFT: f32 = 64.0;
+
+// input black and white points, midtone gamma
+#[derive(Debug, Clone, Copy)]
+pub struct Levels {
+    pub black: f32,
+    pub white: f32,
+    pub gamma: f32,
+}
+
+pub fn parse_levels(levels: &str) -> Result<Levels, String> {
+    let values = levels
+        .split(',')
+        .map(|x| x.trim().parse::<f32>().map_err(|_| format!("invalid number '{}'", x.trim())))
+        .collect::<Result<Vec<f32>, String>>()?;
+
+    let (black, white, gamma) = match values.as_slice() {
+        [black, white] => (*black, *white, 1.0),
+        [black, white, gamma] => (*black, *white, *gamma),
+        _ => return Err("expected \"<black>,<white>[,<gamma>]\"".to_string()),
+    };
+
+    if !(0.0..=255.0).contains(&black) || !(0.0..=255.0).contains(&white) || black >= white {
+        return Err("black and white must be from 0 to 255, black below white".to_string());
+    }
+
+    if !(0.1..=10.0).contains(&gamma) {
+        return Err("gamma must be from 0.1 to 10".to_string());
+    }
+
+    Ok(Levels { black, white, gamma })
+}
+
+// lookup table built from "<in>:<out>" control points
+#[derive(Debug, Clone)]
+pub struct Curve(pub [u8; 256]);
+
+pub fn parse_curve(curve: &str) -> Result<Curve, String> {
+    let points = curve
+        .split(',')
+        .map(|point| {
+            let (x, y) = point
+                .split_once(':')
+                .ok_or_else(|| format!("invalid point '{}', expected <in>:<out>", point.trim()))?;
+
+            match (x.trim().parse::<u8>(), y.trim().parse::<u8>()) {
+                (Ok(x), Ok(y)) => Ok((x as f32, y as f32)),
+                _ => Err(format!("invalid point '{}', values must be from 0 to 255", point.trim())),
+            }
+        })
+        .collect::<Result<Vec<(f32, f32)>, String>>()?;
+
+    if points.len() < 2 {
+        return Err("expected at least 2 points".to_string());
+    }
+
+    if points.windows(2).any(|x| x[0].0 >= x[1].0) {
+        return Err("point inputs must be increasing".to_string());
+    }
+
+    Ok(Curve(monotone_cubic(&points)))
+}
+
+// fritsch-carlson, smooth without overshooting between points, flat outside them
+fn monotone_cubic(points: &[(f32, f32)]) -> [u8; 256] {
+    let n = points.len();
+    let slopes = points
+        .windows(2)
+        .map(|x| (x[1].1 - x[0].1) / (x[1].0 - x[0].0))
+        .collect::<Vec<f32>>();
+
+    let mut tangents = vec![0.0; n];
+    tangents[0] = slopes[0];
+    tangents[n - 1] = slopes[n - 2];
+    for i in 1..n - 1 {
+        if slopes[i - 1] * slopes[i] > 0.0 {
+            tangents[i] = (slopes[i - 1] + slopes[i]) / 2.0;
+        }
+    }
+
+    for i in 0..n - 1 {
+        if slopes[i] == 0.0 {
+            tangents[i] = 0.0;
+            tangents[i + 1] = 0.0;
+            continue;
+        }
+
+        let (a, b) = (tangents[i] / slopes[i], tangents[i + 1] / slopes[i]);
+        let s = a * a + b * b;
+        if s > 9.0 {
+            let t = 3.0 / s.sqrt();
+            tangents[i] = t * a * slopes[i];
+            tangents[i + 1] = t * b * slopes[i];
+        }
+    }
+
+    let mut lut = [0u8; 256];
+    for (x, out) in lut.iter_mut().enumerate() {
+        let x = x as f32;
+        let i = points.windows(2).position(|p| x <= p[1].0).unwrap_or(n - 2);
+        let ((x0, y0), (x1, y1)) = (points[i], points[i + 1]);
+
+        let y = match x {
+            x if x <= x0 => y0,
+            x if x >= x1 => y1,
+            _ => {
+                let h = x1 - x0;
+                let t = (x - x0) / h;
+                let (t2, t3) = (t * t, t * t * t);
+
+                (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+                    + (t3 - 2.0 * t2 + t) * h * tangents[i]
+                    + (-2.0 * t3 + 3.0 * t2) * y1
+                    + (t3 - t2) * h * tangents[i + 1]
+            }
+        };
+
+        *out = y.round().clamp(0.0, 255.0) as u8;
+    }
+
+    lut
+}
+
+// cyan/red, magenta/green, yellow/blue shifts
+#[derive(Debug, Clone, Copy)]
+pub struct Balance(pub [f32; 3]);
+
+pub fn parse_balance(balance: &str) -> Result<Balance, String> {
+    let values = balance
+        .split(',')
+        .map(|x| match x.trim().parse::<f32>() {
+            Ok(x) if (-100.0..=100.0).contains(&x) => Ok(x),
+            _ => Err(format!("invalid value '{}', expected -100 to 100", x.trim())),
+        })
+        .collect::<Result<Vec<f32>, String>>()?;
+
+    match values.as_slice() {
+        [r, g, b] => Ok(Balance([*r, *g, *b])),
+        _ => Err("expected \"<red>,<green>,<blue>\"".to_string()),
+    }
+}
+
+// runs every rgb triple through f, alpha is kept
+pub(crate) fn map_pixels(photon_image: &PhotonImage, f: impl Fn(&mut [u8])) -> PhotonImage {
+    let mut pixels = photon_image.get_raw_pixels();
+    for pixel in pixels.chunks_mut(4) {
+        f(&mut pixel[..3]);
+    }
+
+    PhotonImage::new(pixels, photon_image.get_width(), photon_image.get_height())
+}
+
+pub fn levels(photon_image: &PhotonImage, levels: &Levels) -> PhotonImage {
+    let mut lut = [0u8; 256];
+    for (i, out) in lut.iter_mut().enumerate() {
+        let x = ((i as f32 - levels.black) / (levels.white - levels.black)).clamp(0.0, 1.0);
+        *out = (x.powf(1.0 / levels.gamma) * 255.0).round() as u8;
+    }
+
+    map_pixels(photon_image, |rgb| rgb.iter_mut().for_each(|x| *x = lut[*x as usize]))
+}
+
+// curves per channel, None leaves the channel alone
+pub fn curves(photon_image: &PhotonImage, channels: [Option<&Curve>; 3]) -> PhotonImage {
+    map_pixels(photon_image, |rgb| {
+        for (x, curve) in rgb.iter_mut().zip(channels) {
+            if let Some(curve) = curve {
+                *x = curve.0[*x as usize];
+            }
+        }
+    })
+}
+
+// each range is weighted by luma, shadows fade out by mid gray and highlights fade in from it
+pub fn color_balance(
+    photon_image: &PhotonImage,
+    shadows: Option<&Balance>,
+    midtones: Option<&Balance>,
+    highlights: Option<&Balance>,
+) -> PhotonImage {
+    let zero = Balance([0.0; 3]);
+    let ranges = [shadows.unwrap_or(&zero), midtones.unwrap_or(&zero), highlights.unwrap_or(&zero)];
+
+    map_pixels(photon_image, |rgb| {
+        let hex = ((rgb[0] as u32) << 16) + ((rgb[1] as u32) << 8) + rgb[2] as u32;
+        let l = luma(hex) / 255.0;
+
+        let shadow = (1.0 - 2.0 * l).clamp(0.0, 1.0);
+        let highlight = (2.0 * l - 1.0).clamp(0.0, 1.0);
+        let weights = [shadow, 1.0 - shadow - highlight, highlight];
+
+        for (c, x) in rgb.iter_mut().enumerate() {
+            let shift = ranges
+                .iter()
+                .zip(weights)
+                .map(|(range, weight)| range.0[c] / 100.0 * BALANCE_SHIFT * weight)
+                .sum::<f32>();
+
+            *x = (*x as f32 + shift).round().clamp(0.0, 255.0) as u8;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curve_stays_monotone() {
+        for points in ["0:0,255:255", "0:0,64:200,128:210,255:255", "0:20,30:25,60:240,200:250,255:255"] {
+            let curve = parse_curve(points).unwrap();
+            assert!(curve.0.windows(2).all(|x| x[0] <= x[1]), "{}", points);
+        }
+
+        let curve = parse_curve("0:0,64:200,128:210,255:255").unwrap();
+        assert_eq!((curve.0[0], curve.0[64], curve.0[128], curve.0[255]), (0, 200, 210, 255));
+    }
+
+    #[test]
+    fn curve_flat_outside_points() {
+        let curve = parse_curve("50:10,200:240").unwrap();
+        assert!(curve.0[..=50].iter().all(|&x| x == 10));
+        assert!(curve.0[200..].iter().all(|&x| x == 240));
+    }
+
+    #[test]
+    fn malformed() {
+        for curve in ["", "0:0", "0:0,255", "0:0,300:255", "0:0,a:b", "128:0,64:255", "64:0,64:255"] {
+            assert!(parse_curve(curve).is_err(), "{}", curve);
+        }
+
+        for levels in ["", "10", "200,100", "0,255,0", "0,300"] {
+            assert!(parse_levels(levels).is_err(), "{}", levels);
+        }
+
+        for balance in ["", "0,0", "0,0,101", "a,0,0"] {
+            assert!(parse_balance(balance).is_err(), "{}", balance);
+        }
+    }
+}